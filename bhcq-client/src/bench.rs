@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::time::{Duration, Instant};
+use dhcpv4::{Message, OpCode};
+use dhcpv4::options::OptionSet;
+use dhcpv4::options::{
+    message_type::*,
+    server_identifier::*,
+};
+
+use super::{bind, build, Args};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Discovering,
+    Requesting,
+    Done,
+}
+
+struct Exchange {
+    phase: Phase,
+    started: Instant,
+    deadline: Instant,
+}
+
+#[derive(Default)]
+struct Report {
+    started: u32,
+    offers: u32,
+    acks: u32,
+    naks: u32,
+    no_offer: u32,
+    no_ack: u32,
+    // Addresses acknowledged to a client while another still held them.
+    duplicates: u32,
+    // How many leases had been acknowledged when a DHCPDISCOVER first went
+    // unanswered: where the pool ran out, if nothing got dropped before.
+    first_no_offer: Option<u32>,
+    offer_latencies: Vec<Duration>,
+    latencies: Vec<Duration>,
+    elapsed: Duration,
+}
+
+struct Bench {
+    args: Args,
+    sock: UdpSocket,
+    base_mac: Vec<u8>,
+    base_xid: u32,
+    exchanges: Vec<Option<Exchange>>,
+    // Clients with an exchange in progress.
+    in_flight: Vec<u32>,
+    holders: HashMap<Ipv4Addr, u32>,
+    report: Report,
+}
+
+impl Bench {
+    // The base hardware address plus `client`, counting in its last four
+    // bytes at most.
+    fn mac(&self, client: u32) -> Vec<u8> {
+        let mut mac = self.base_mac.clone();
+        let tail = mac.len().saturating_sub(4);
+        let len = mac.len() - tail;
+        let mut n = [0u8; 4];
+        n[4 - len..].copy_from_slice(&mac[tail..]);
+        let n = u32::from_be_bytes(n).wrapping_add(client).to_be_bytes();
+        mac[tail..].copy_from_slice(&n[4 - len..]);
+        mac
+    }
+
+    fn send(&mut self, client: u32, message_type: MessageType, addr: Option<Ipv4Addr>, server_identifier: Option<Ipv4Addr>) -> Result<(), Box<dyn StdError>> {
+        self.args.mac = self.mac(client);
+        let args = &mut self.args;
+        args.xid = self.base_xid.wrapping_add(client);
+        args.message_type = message_type;
+        args.ciaddr = Ipv4Addr::UNSPECIFIED;
+        args.requested_ip_address = None;
+        match message_type {
+            MessageType::DHCPRELEASE => args.ciaddr = addr.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            _ => args.requested_ip_address = addr,
+        }
+        args.server_identifier = server_identifier;
+        let packet = build(args)?;
+        self.sock.send_to(&packet, (args.server, args.port))?;
+        Ok(())
+    }
+
+    fn start(&mut self, client: u32, now: Instant) -> Result<(), Box<dyn StdError>> {
+        self.send(client, MessageType::DHCPDISCOVER, None, None)?;
+        self.exchanges[client as usize] = Some(Exchange {
+            phase: Phase::Discovering,
+            started: now,
+            deadline: now + self.args.timeout,
+        });
+        self.in_flight.push(client);
+        self.report.started += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, client: u32) {
+        if let Some(exchange) = &mut self.exchanges[client as usize] {
+            exchange.phase = Phase::Done;
+        }
+        self.in_flight.retain(|&c| c != client);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let expired: Vec<u32> = self.in_flight.iter()
+            .copied()
+            .filter(|&c| self.exchanges[c as usize].as_ref().is_some_and(|e| e.deadline <= now))
+            .collect();
+        for client in expired {
+            match self.exchanges[client as usize].as_ref().map(|e| e.phase) {
+                Some(Phase::Discovering) => {
+                    self.report.no_offer += 1;
+                    if self.report.first_no_offer.is_none() {
+                        self.report.first_no_offer = Some(self.report.acks);
+                    }
+                },
+                Some(Phase::Requesting) => self.report.no_ack += 1,
+                _ => {},
+            }
+            self.finish(client);
+        }
+    }
+
+    fn handle(&mut self, bytes: &[u8], now: Instant) -> Result<(), Box<dyn StdError>> {
+        let m = match Message::new(bytes) {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+        let hdr = m.header();
+        let client = hdr.xid().wrapping_sub(self.base_xid);
+        if hdr.op_code() != OpCode::BOOTREPLY || client >= self.args.clients {
+            return Ok(());
+        }
+        let (phase, started) = match &self.exchanges[client as usize] {
+            Some(exchange) => (exchange.phase, exchange.started),
+            None => return Ok(()),
+        };
+        let opts = match OptionSet::from_message(&m) {
+            Some(opts) => opts,
+            None => return Ok(()),
+        };
+        let yiaddr = hdr.yiaddr();
+        match (phase, opts.get_message_type()) {
+            (Phase::Discovering, Some(MessageType::DHCPOFFER)) => {
+                self.report.offers += 1;
+                self.report.offer_latencies.push(now - started);
+                let server_identifier = opts.get_server_identifier();
+                self.send(client, MessageType::DHCPREQUEST, Some(yiaddr), server_identifier)?;
+                if let Some(exchange) = &mut self.exchanges[client as usize] {
+                    exchange.phase = Phase::Requesting;
+                    exchange.deadline = now + self.args.timeout;
+                }
+            },
+            (Phase::Requesting, Some(MessageType::DHCPACK)) => {
+                self.report.acks += 1;
+                self.report.latencies.push(now - started);
+                if let Some(holder) = self.holders.insert(yiaddr, client) {
+                    if holder != client {
+                        self.report.duplicates += 1;
+                    }
+                }
+                if self.args.release {
+                    let server_identifier = opts.get_server_identifier();
+                    self.send(client, MessageType::DHCPRELEASE, Some(yiaddr), server_identifier)?;
+                    self.holders.remove(&yiaddr);
+                }
+                self.finish(client);
+            },
+            (Phase::Requesting, Some(MessageType::DHCPNAK)) => {
+                self.report.naks += 1;
+                self.finish(client);
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // When the next client may start, given the rate limit.
+    fn next_start(&self, begin: Instant) -> Instant {
+        match self.args.rate {
+            0 => begin,
+            rate => begin + Duration::from_secs_f64(f64::from(self.report.started) / f64::from(rate)),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), Box<dyn StdError>> {
+        let begin = Instant::now();
+        let mut buf = [0u8; 1500];
+        let mut next = 0;
+        loop {
+            let now = Instant::now();
+            while next < self.args.clients
+                && (self.in_flight.len() as u32) < self.args.concurrency
+                && self.next_start(begin) <= now
+            {
+                self.start(next, now)?;
+                next += 1;
+            }
+            self.expire(now);
+            if next == self.args.clients && self.in_flight.is_empty() {
+                break;
+            }
+            let mut wake = self.in_flight.iter()
+                .filter_map(|&c| self.exchanges[c as usize].as_ref().map(|e| e.deadline))
+                .min()
+                .unwrap_or(now + self.args.timeout);
+            if next < self.args.clients && (self.in_flight.len() as u32) < self.args.concurrency {
+                wake = wake.min(self.next_start(begin));
+            }
+            // A zero read timeout means no timeout at all.
+            let wait = wake.saturating_duration_since(now).max(Duration::from_micros(100));
+            self.sock.set_read_timeout(Some(wait))?;
+            match self.sock.recv_from(&mut buf) {
+                Ok((len, _)) => self.handle(&buf[..len], Instant::now())?,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.report.elapsed = begin.elapsed();
+        Ok(())
+    }
+}
+
+// The nearest-rank `p`th percentile of sorted `values`.
+fn percentile(values: &[Duration], p: usize) -> Duration {
+    if values.is_empty() {
+        return Duration::from_secs(0);
+    }
+    let rank = (p * values.len()).div_ceil(100).max(1);
+    values[rank - 1]
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+impl Report {
+    fn print(&mut self, args: &Args) {
+        let rate = |n: u32| 100.0 * f64::from(n) / f64::from(self.started.max(1));
+        let lost = self.no_offer + self.no_ack;
+        println!("clients          {}", args.clients);
+        println!("leases           {} ({:.1}%)", self.acks, rate(self.acks));
+        println!("offers           {}", self.offers);
+        println!("naks             {}", self.naks);
+        println!("no offer         {}", self.no_offer);
+        println!("no ack           {}", self.no_ack);
+        println!("drop rate        {:.1}%", rate(lost));
+        println!("duplicate leases {}", self.duplicates);
+        match self.first_no_offer {
+            Some(leases) => println!("pool exhausted   after {} leases, or a DISCOVER was dropped", leases),
+            None => println!("pool exhausted   no"),
+        }
+        let secs = self.elapsed.as_secs_f64();
+        println!("elapsed          {:.3}s", secs);
+        println!("throughput       {:.1} DORA/s", f64::from(self.acks) / secs.max(1e-9));
+        for (name, values) in &mut [("offer latency", &mut self.offer_latencies), ("DORA latency", &mut self.latencies)] {
+            values.sort();
+            println!(
+                "{:16} p50 {:.2}ms  p90 {:.2}ms  p99 {:.2}ms  max {:.2}ms",
+                name,
+                millis(percentile(values, 50)),
+                millis(percentile(values, 90)),
+                millis(percentile(values, 99)),
+                millis(percentile(values, 100)),
+            );
+        }
+    }
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn StdError>> {
+    let sock = bind(&args)?;
+    let mut bench = Bench {
+        base_mac: args.mac.clone(),
+        base_xid: args.xid,
+        exchanges: (0..args.clients).map(|_| None).collect(),
+        in_flight: Vec::new(),
+        holders: HashMap::new(),
+        report: Report::default(),
+        args,
+        sock,
+    };
+    bench.run()?;
+    bench.report.print(&bench.args);
+    Ok(())
+}
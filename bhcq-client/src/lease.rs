@@ -0,0 +1,161 @@
+use std::error::Error as StdError;
+use std::io;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+use dhcpv4::{Client, Message, OpCode};
+use dhcpv4::client::{Event, Lease, Transmit};
+use dhcpv4::message;
+use dhcpv4::option::Code;
+use dhcpv4::options::OptionSet;
+use dhcpv4::options::{
+    end::*,
+    message_type::*,
+    relay_agent_information::*,
+};
+
+use super::{bind, print_reply, Args};
+
+// Acquires a lease through `dhcpv4::Client`, printing the replies on the
+// way, and with --release gives it back. A --requested-ip is asked for
+// again as from INIT-REBOOT.
+pub fn run(args: Args) -> Result<(), Box<dyn StdError>> {
+    let sock = bind(&args)?;
+    let begin = Instant::now();
+    let deadline = begin + args.timeout;
+    let mut client = Client::new(&args.mac, args.xid, begin);
+    if let Some(id) = &args.client_identifier {
+        client.set_client_identifier(id).map_err(|e| format!("client identifier: {}", e))?;
+    }
+    if !args.parameter_request_list.is_empty() {
+        client.set_parameter_request_list(&args.parameter_request_list)
+            .map_err(|e| format!("parameter request list: {}", e))?;
+    }
+    if let Some(addr) = args.requested_ip_address {
+        client.reboot(addr);
+    }
+    client.start(begin);
+    let mut buf = [0u8; 1500];
+    loop {
+        while let Some(event) = client.poll_event() {
+            match event {
+                Event::Transmit(transmit) => send(&sock, &args, &transmit)?,
+                Event::Bound(lease) => {
+                    print_lease(&lease);
+                    if args.release {
+                        client.release(Instant::now());
+                        while let Some(event) = client.poll_event() {
+                            if let Event::Transmit(transmit) = event {
+                                send(&sock, &args, &transmit)?;
+                            }
+                        }
+                    }
+                    return Ok(());
+                },
+                Event::Lost(lease) => println!("lost {}", lease.address),
+            }
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err("no lease".into());
+        }
+        let wake = client.poll_timeout().map_or(deadline, |timeout| timeout.min(deadline));
+        let left = wake.saturating_duration_since(now);
+        if left == Duration::from_secs(0) {
+            client.handle_timeout(now);
+            continue;
+        }
+        sock.set_read_timeout(Some(left))?;
+        match sock.recv_from(&mut buf) {
+            Ok((len, peer)) => {
+                let bytes = &buf[..len];
+                // Our own broadcast, or someone else's exchange.
+                match Message::new(bytes) {
+                    Some(m) if m.header().op_code() == OpCode::BOOTREPLY && m.header().chaddr().starts_with(&args.mac) => {},
+                    _ => continue,
+                }
+                print_reply(bytes, peer);
+                client.handle(bytes, Instant::now());
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                client.handle_timeout(Instant::now());
+            },
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// Sends to --server if one was given, and otherwise where the client
+// wants.
+fn send(sock: &UdpSocket, args: &Args, transmit: &Transmit) -> Result<(), Box<dyn StdError>> {
+    let packet = relayed(args, &transmit.packet)?;
+    let dest = transmit.destination
+        .filter(|_| args.server.is_broadcast())
+        .unwrap_or(args.server);
+    sock.send_to(&packet, (dest, args.port))?;
+    let m = Message::new(&packet).ok_or("client built a short message")?;
+    let typ = match OptionSet::from_message(&m).and_then(|opts| opts.get_message_type()) {
+        Some(MessageType::DHCPDISCOVER) => "DHCPDISCOVER",
+        Some(MessageType::DHCPREQUEST) => "DHCPREQUEST",
+        Some(MessageType::DHCPDECLINE) => "DHCPDECLINE",
+        Some(MessageType::DHCPRELEASE) => "DHCPRELEASE",
+        _ => "message",
+    };
+    println!("sent {} ({} bytes) to {}:{}, xid 0x{:08x}", typ, packet.len(), dest, args.port, m.header().xid());
+    Ok(())
+}
+
+// The client's message as it leaves us: with the broadcast flag if asked
+// for, and as a relay agent would pass it on if --giaddr is given.
+fn relayed(args: &Args, packet: &[u8]) -> Result<Vec<u8>, Box<dyn StdError>> {
+    let agent = args.circuit_id.is_some() || args.remote_id.is_some();
+    if !args.broadcast && args.giaddr.is_unspecified() && !agent {
+        return Ok(packet.to_vec());
+    }
+    let m = Message::new(packet).ok_or("client built a short message")?;
+    let mut set = OptionSet::from_message(&m).ok_or("client built a message without options")?;
+    let mut bldr = message::Builder::new();
+    bldr.header_mut().as_mut_slice().copy_from_slice(m.header().as_slice());
+    let mut hdr = bldr.header_mut();
+    // The options are written out again, so drop those that overflowed
+    // into `sname` and `file`.
+    if set.remove(Code::OVERLOAD).is_some() {
+        hdr.sname().iter_mut().for_each(|b| *b = 0);
+        hdr.file().iter_mut().for_each(|b| *b = 0);
+    }
+    if args.broadcast {
+        hdr.set_flags(0x8000);
+    }
+    if !args.giaddr.is_unspecified() {
+        hdr.set_giaddr(args.giaddr);
+        hdr.set_hops(1);
+    }
+    if agent {
+        let mut info = RelayAgentInformation::new();
+        if let Some(id) = &args.circuit_id {
+            info.push(SubCode::AGENT_CIRCUIT_ID, id).ok_or("circuit ID is too long")?;
+        }
+        if let Some(id) = &args.remote_id {
+            info.push(SubCode::AGENT_REMOTE_ID, id).ok_or("remote ID is too long")?;
+        }
+        set.add_relay_agent_information(&info);
+    }
+    {
+        let mut opts = bldr.options_builder();
+        opts.add_magic_cookie();
+        set.write(&mut opts);
+        opts.add_end();
+    }
+    Ok(bldr.finish_owned()?)
+}
+
+fn print_lease(lease: &Lease) {
+    let secs = |time: Option<Duration>| time.map_or("never".to_owned(), |time| format!("{}s", time.as_secs()));
+    println!(
+        "bound to {} from {}: lease {}, renew {}, rebind {}",
+        lease.address,
+        lease.server_identifier,
+        secs(lease.lease_time),
+        secs(lease.renew_time),
+        secs(lease.rebinding_time),
+    );
+}
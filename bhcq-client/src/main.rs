@@ -0,0 +1,391 @@
+use nix::errno::Errno;
+use nix::sys::socket::{self, AddressFamily, SockAddr, SockFlag, SockProtocol, SockType, sockopt};
+use std::error::Error as StdError;
+use std::ffi::CString;
+use std::net::{self, Ipv4Addr, SocketAddr};
+use std::os::unix::io::FromRawFd;
+use std::time::{Duration, Instant};
+use dhcpv4::{Message, OpCode};
+use dhcpv4::message;
+use dhcpv4::option::Code;
+use dhcpv4::options::{DhcpOption, OptionSet};
+use dhcpv4::options::{
+    client_identifier::*,
+    end::*,
+    message_type::*,
+    parameter_request_list::*,
+    relay_agent_information::*,
+    requested_ip_address::*,
+    server_identifier::*,
+};
+
+mod bench;
+mod lease;
+
+const USAGE: &str = "\
+usage: bhcq-client [OPTIONS] discover|request|release|inform|decline|lease|bench
+
+options:
+    --server ADDR         where to send the request [255.255.255.255]
+    --port PORT           server port; replies come to PORT + 1 [67]
+    --interface NAME      send and receive through this interface only
+    --mac MAC             client hardware address [02:00:00:00:00:01]
+    --client-id HEX       client identifier, as colon-separated hex
+    --options CODES       parameter request list, e.g. 1,3,6
+    --giaddr ADDR         act as a relay agent at ADDR, which gets the reply
+    --circuit-id HEX      relay agent circuit ID (option 82)
+    --remote-id HEX       relay agent remote ID (option 82)
+    --ciaddr ADDR         client address
+    --requested-ip ADDR   requested IP address option
+    --server-id ADDR      server identifier option
+    --broadcast           set the broadcast flag
+    --xid XID             transaction ID [random]
+    --timeout SECS        how long to wait for replies [3]
+
+lease acquires a lease the way a client does, retransmitting with backoff
+until --timeout runs out, and prints it; --requested-ip asks for an address
+held before, and --release gives the lease back at once.
+
+bench runs DISCOVER/REQUEST exchanges for many clients, each with the
+hardware address --mac plus its number, and reports how they went:
+    --clients N           how many clients to simulate [1000]
+    --concurrency N       how many exchanges to keep in flight [64]
+    --rate N              exchanges to start per second, 0 for no limit [0]
+    --release             release each lease once acquired";
+
+enum Command {
+    Send,
+    Lease,
+    Bench,
+}
+
+struct Args {
+    command: Command,
+    message_type: MessageType,
+    server: Ipv4Addr,
+    port: u16,
+    interface: Option<String>,
+    mac: Vec<u8>,
+    client_identifier: Option<Vec<u8>>,
+    parameter_request_list: Vec<Code>,
+    giaddr: Ipv4Addr,
+    circuit_id: Option<Vec<u8>>,
+    remote_id: Option<Vec<u8>>,
+    ciaddr: Ipv4Addr,
+    requested_ip_address: Option<Ipv4Addr>,
+    server_identifier: Option<Ipv4Addr>,
+    broadcast: bool,
+    xid: u32,
+    timeout: Duration,
+    clients: u32,
+    concurrency: u32,
+    rate: u32,
+    release: bool,
+}
+
+impl Args {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut parsed = Args {
+            command: Command::Send,
+            message_type: MessageType::DHCPDISCOVER,
+            server: Ipv4Addr::BROADCAST,
+            port: 67,
+            interface: None,
+            mac: vec![0x02, 0, 0, 0, 0, 0x01],
+            client_identifier: None,
+            parameter_request_list: Vec::new(),
+            giaddr: Ipv4Addr::UNSPECIFIED,
+            circuit_id: None,
+            remote_id: None,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            requested_ip_address: None,
+            server_identifier: None,
+            broadcast: false,
+            xid: random_xid(),
+            timeout: Duration::from_secs(3),
+            clients: 1000,
+            concurrency: 64,
+            rate: 0,
+            release: false,
+        };
+        let mut message_type = None;
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                if message_type.is_some() {
+                    return Err(format!("unexpected argument `{}`", arg));
+                }
+                if arg == "bench" || arg == "lease" {
+                    parsed.command = if arg == "bench" { Command::Bench } else { Command::Lease };
+                    message_type = Some(MessageType::DHCPDISCOVER);
+                } else {
+                    message_type = Some(parse_message_type(&arg)?);
+                }
+                continue;
+            }
+            if arg == "--help" {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            if arg == "--broadcast" {
+                parsed.broadcast = true;
+                continue;
+            }
+            if arg == "--release" {
+                parsed.release = true;
+                continue;
+            }
+            let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+            let invalid = || format!("invalid value `{}` for {}", value, arg);
+            match &arg[..] {
+                "--server" => parsed.server = value.parse().map_err(|_| invalid())?,
+                "--port" => parsed.port = value.parse().ok().filter(|&p| p != 0 && p != u16::MAX).ok_or_else(invalid)?,
+                "--interface" => parsed.interface = Some(value.clone()),
+                "--mac" => parsed.mac = parse_hex(&value).filter(|mac| mac.len() <= 16).ok_or_else(invalid)?,
+                "--client-id" => parsed.client_identifier = Some(parse_hex(&value).ok_or_else(invalid)?),
+                "--circuit-id" | "--remote-id" => {
+                    let id = parse_hex(&value)
+                        .filter(|id| id.len() <= RelayAgentInformation::MAX_SUB_OPTION_LEN)
+                        .ok_or_else(invalid)?;
+                    match &arg[..] {
+                        "--circuit-id" => parsed.circuit_id = Some(id),
+                        _ => parsed.remote_id = Some(id),
+                    }
+                },
+                "--options" => {
+                    parsed.parameter_request_list = value.split(',')
+                        .map(|code| code.parse().map(Code))
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| invalid())?;
+                },
+                "--giaddr" => parsed.giaddr = value.parse().map_err(|_| invalid())?,
+                "--ciaddr" => parsed.ciaddr = value.parse().map_err(|_| invalid())?,
+                "--requested-ip" => parsed.requested_ip_address = Some(value.parse().map_err(|_| invalid())?),
+                "--server-id" => parsed.server_identifier = Some(value.parse().map_err(|_| invalid())?),
+                "--xid" => parsed.xid = parse_xid(&value).ok_or_else(invalid)?,
+                "--timeout" => {
+                    let secs: f64 = value.parse().ok().filter(|&secs: &f64| secs > 0.0 && secs < 1e6).ok_or_else(invalid)?;
+                    parsed.timeout = Duration::from_secs_f64(secs);
+                },
+                "--clients" => parsed.clients = value.parse().ok().filter(|&n| n > 0).ok_or_else(invalid)?,
+                "--concurrency" => parsed.concurrency = value.parse().ok().filter(|&n| n > 0).ok_or_else(invalid)?,
+                "--rate" => parsed.rate = value.parse().map_err(|_| invalid())?,
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        parsed.message_type = message_type.ok_or("no message type given")?;
+        Ok(parsed)
+    }
+}
+
+fn parse_message_type(name: &str) -> Result<MessageType, String> {
+    match name {
+        "discover" => Ok(MessageType::DHCPDISCOVER),
+        "request" => Ok(MessageType::DHCPREQUEST),
+        "release" => Ok(MessageType::DHCPRELEASE),
+        "inform" => Ok(MessageType::DHCPINFORM),
+        "decline" => Ok(MessageType::DHCPDECLINE),
+        _ => Err(format!("unknown message type `{}`", name)),
+    }
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    text.split(':')
+        .map(|byte| match byte.len() {
+            1 | 2 => u8::from_str_radix(byte, 16).ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .filter(|bytes| !bytes.is_empty())
+}
+
+fn parse_xid(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// Good enough to tell runs apart; nothing depends on it being unpredictable.
+fn random_xid() -> u32 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    nanos ^ std::process::id().rotate_left(16)
+}
+
+fn build(args: &Args) -> Result<Vec<u8>, Box<dyn StdError>> {
+    let mut bldr = message::Builder::new();
+    let mut hdr = bldr.header_mut();
+    hdr.set_op_code(OpCode::BOOTREQUEST);
+    hdr.set_hlen(args.mac.len() as u8);
+    hdr.set_xid(args.xid);
+    hdr.set_flags(if args.broadcast { 0x8000 } else { 0 });
+    hdr.set_ciaddr(args.ciaddr);
+    hdr.set_giaddr(args.giaddr);
+    if !args.giaddr.is_unspecified() {
+        hdr.set_hops(1);
+    }
+    hdr.chaddr()[..args.mac.len()].copy_from_slice(&args.mac);
+    let mut set = OptionSet::new();
+    set.add_message_type(args.message_type);
+    if let Some(client_identifier) = &args.client_identifier {
+        set.add_client_identifier(client_identifier);
+    }
+    if let Some(addr) = args.requested_ip_address {
+        set.add_requested_ip_address(addr);
+    }
+    if let Some(addr) = args.server_identifier {
+        set.add_server_identifier(addr);
+    }
+    if !args.parameter_request_list.is_empty() {
+        set.add_parameter_request_list(args.parameter_request_list.iter().copied());
+    }
+    // Relay agents append theirs after the client's options.
+    if args.circuit_id.is_some() || args.remote_id.is_some() {
+        let mut info = RelayAgentInformation::new();
+        if let Some(id) = &args.circuit_id {
+            info.push(SubCode::AGENT_CIRCUIT_ID, id).ok_or("circuit ID is too long")?;
+        }
+        if let Some(id) = &args.remote_id {
+            info.push(SubCode::AGENT_REMOTE_ID, id).ok_or("remote ID is too long")?;
+        }
+        set.add_relay_agent_information(&info);
+    }
+    {
+        let mut opts = bldr.options_builder();
+        opts.add_magic_cookie();
+        set.write(&mut opts);
+        opts.add_end();
+    }
+    Ok(bldr.finish_owned()?)
+}
+
+// A relay agent gets the reply on the server port at its own address; a
+// client gets it on the client port.
+fn bind(args: &Args) -> Result<net::UdpSocket, Box<dyn StdError>> {
+    let fd = socket::socket(
+        AddressFamily::Inet,
+        SockType::Datagram,
+        SockFlag::empty(),
+        Some(SockProtocol::Udp),
+    )?;
+    // The server may be listening on the same port on this host.
+    socket::setsockopt(fd, sockopt::ReuseAddr, &true)?;
+    if let Some(name) = &args.interface {
+        let ifname = CString::new(name.clone())?;
+        unsafe {
+            let res = libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                ifname.as_ptr() as *const libc::c_void,
+                ifname.as_bytes().len() as u32,
+            );
+            Errno::result(res).map(drop)
+        }?;
+    }
+    let addr: SocketAddr = if args.giaddr.is_unspecified() {
+        (Ipv4Addr::UNSPECIFIED, args.port + 1).into()
+    } else {
+        (args.giaddr, args.port).into()
+    };
+    socket::bind(fd, &SockAddr::Inet(socket::InetAddr::from_std(&addr)))
+        .map_err(|e| format!("cannot bind {}: {}", addr, e))?;
+    let sock = unsafe { net::UdpSocket::from_raw_fd(fd) };
+    sock.set_broadcast(true)?;
+    Ok(sock)
+}
+
+fn print_reply(bytes: &[u8], peer: SocketAddr) {
+    let m = match Message::parse(bytes) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("malformed reply from {}: {}", peer, e);
+            return;
+        },
+    };
+    let hdr = m.header();
+    let opts = OptionSet::from_message(&m).unwrap_or_default();
+    let message_type = match opts.get_message_type() {
+        Some(MessageType::DHCPOFFER) => "DHCPOFFER".to_owned(),
+        Some(MessageType::DHCPACK) => "DHCPACK".to_owned(),
+        Some(MessageType::DHCPNAK) => "DHCPNAK".to_owned(),
+        Some(MessageType(typ)) => format!("message type {}", typ),
+        None => "BOOTP reply".to_owned(),
+    };
+    println!("{} from {}", message_type, peer);
+    println!("  xid 0x{:08x}  secs {}  flags 0x{:04x}  hops {}", hdr.xid(), hdr.secs(), hdr.flags(), hdr.hops());
+    println!("  ciaddr {}  yiaddr {}  siaddr {}  giaddr {}", hdr.ciaddr(), hdr.yiaddr(), hdr.siaddr(), hdr.giaddr());
+    println!("  chaddr {:02x?}", &hdr.chaddr()[..usize::from(hdr.hlen()).min(16)]);
+    for (name, field) in &[("sname", hdr.sname()), ("file", hdr.file())] {
+        let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+        if len > 0 {
+            println!("  {} {:?}", name, String::from_utf8_lossy(&field[..len]));
+        }
+    }
+    for (Code(code), value) in opts.iter() {
+        match DhcpOption::parse(Code(code), value) {
+            Some(DhcpOption::Unknown(..)) | None => println!("  option {:3} {:02x?}", code, value),
+            Some(opt) => println!("  option {:3} {:?}", code, opt),
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn StdError>> {
+    let packet = build(&args)?;
+    let sock = bind(&args)?;
+    sock.send_to(&packet, (args.server, args.port))?;
+    println!("sent {} bytes to {}:{}, xid 0x{:08x}", packet.len(), args.server, args.port, args.xid);
+    let expects_reply = args.message_type != MessageType::DHCPRELEASE && args.message_type != MessageType::DHCPDECLINE;
+    if !expects_reply {
+        return Ok(());
+    }
+    // Wait out the whole timeout: a DHCPDISCOVER may draw several offers.
+    let deadline = Instant::now() + args.timeout;
+    let mut buf = [0u8; 1500];
+    let mut replies = 0;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::from_secs(0) {
+            break;
+        }
+        sock.set_read_timeout(Some(left))?;
+        let (len, peer) = match sock.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => break,
+            Err(e) => return Err(e.into()),
+        };
+        let bytes = &buf[..len];
+        // Our own broadcast, or someone else's exchange.
+        match Message::new(bytes) {
+            Some(m) if m.header().op_code() == OpCode::BOOTREPLY && m.header().xid() == args.xid => {},
+            _ => continue,
+        }
+        print_reply(bytes, peer);
+        replies += 1;
+    }
+    if replies == 0 {
+        return Err("no reply".into());
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("bhcq-client: {}\n{}", e, USAGE);
+            std::process::exit(2);
+        },
+    };
+    let res = match args.command {
+        Command::Send => run(args),
+        Command::Lease => lease::run(args),
+        Command::Bench => bench::run(args),
+    };
+    if let Err(e) = res {
+        eprintln!("bhcq-client: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,703 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+
+use dhcpv4::option::Code;
+use dhcpv4::options::relay_agent_information::RelayAgentInformation;
+use dhcpv4::parser::Mode;
+use serde::Deserialize;
+
+use super::pool::Pool;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Ipv4Net {
+    addr: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Ipv4Net {
+    // Host bits of `addr` are cleared, so `10.0.0.5/24` and `10.0.0.0/24`
+    // are the same network.
+    pub fn new(addr: Ipv4Addr, prefix_len: u8) -> Option<Self> {
+        if prefix_len > 32 {
+            return None;
+        }
+        let mask = u32::MAX.checked_shl(32 - u32::from(prefix_len)).unwrap_or(0);
+        let addr = (u32::from(addr) & mask).into();
+        Some(Self { addr, prefix_len })
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn mask(&self) -> Ipv4Addr {
+        u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0).into()
+    }
+
+    pub fn network(&self) -> Ipv4Addr {
+        self.addr
+    }
+
+    pub fn broadcast(&self) -> Ipv4Addr {
+        (u32::from(self.addr) | !u32::from(self.mask())).into()
+    }
+
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        u32::from(addr) & u32::from(self.mask()) == u32::from(self.addr)
+    }
+
+    pub fn overlaps(&self, other: &Ipv4Net) -> bool {
+        self.contains(other.addr) || other.contains(self.addr)
+    }
+
+    pub fn contains_host(&self, addr: Ipv4Addr) -> bool {
+        self.contains(addr) && addr != self.network() && addr != self.broadcast()
+    }
+}
+
+impl fmt::Display for Ipv4Net {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl TryFrom<String> for Ipv4Net {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let err = || format!("invalid network `{}`, expected a.b.c.d/len", s);
+        let (addr, len) = s.split_once('/').ok_or_else(err)?;
+        let addr = addr.parse().map_err(|_| err())?;
+        let len = len.parse().map_err(|_| err())?;
+        Self::new(addr, len).ok_or_else(err)
+    }
+}
+
+// Bytes written as colon-separated hex octets, e.g. `52:54:00:12:34:56`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HexBytes(pub Vec<u8>);
+
+impl fmt::Display for HexBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for HexBytes {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let err = || format!("invalid octets `{}`, expected hex bytes separated by colons", s);
+        s.split(':')
+            .map(|octet| match octet.len() {
+                1 | 2 => u8::from_str_radix(octet, 16).ok(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(HexBytes)
+            .ok_or_else(err)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // Required unless running as a relay agent.
+    #[serde(default)]
+    pub lease_file: PathBuf,
+    #[serde(default = "Config::default_decline_time")]
+    pub decline_time: u32,
+    // The server port. Clients are reached on the next one up, as with 67
+    // and 68.
+    #[serde(default = "Config::default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub parse_mode: ParseMode,
+    #[serde(default)]
+    pub options: OptionSet,
+    #[serde(default, rename = "interface")]
+    pub interfaces: Vec<Interface>,
+    #[serde(default, rename = "subnet")]
+    pub subnets: Vec<Subnet>,
+    // Run as a relay agent (RFC 1542) instead of a server.
+    pub relay: Option<Relay>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Interface {
+    pub name: String,
+    pub server_identifier: Ipv4Addr,
+    // Reach clients without an address through an AF_PACKET socket instead
+    // of adding ARP entries for them.
+    #[serde(default)]
+    pub raw_socket: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Relay {
+    // Where requests are forwarded, every one to each.
+    pub servers: Vec<Ipv4Addr>,
+    // Requests that have already been through this many relay agents are
+    // dropped.
+    #[serde(default = "Relay::default_max_hops")]
+    pub max_hops: u8,
+    // The links clients are on.
+    #[serde(rename = "interface")]
+    pub interfaces: Vec<RelayInterface>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelayInterface {
+    pub name: String,
+    // Our address on the link, which goes into giaddr.
+    pub address: Ipv4Addr,
+    #[serde(default)]
+    pub raw_socket: bool,
+}
+
+impl Relay {
+    pub const DEFAULT_MAX_HOPS: u8 = 4;
+    // RFC 1542, section 4.1.1.
+    pub const MAX_HOPS_LIMIT: u8 = 16;
+
+    fn default_max_hops() -> u8 {
+        Self::DEFAULT_MAX_HOPS
+    }
+
+    pub fn interface(&self, name: &str) -> Option<&RelayInterface> {
+        self.interfaces.iter().find(|iface| iface.name == name)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.servers.is_empty() {
+            return Err("relay has no server to forward to".to_owned());
+        }
+        if let Some(server) = self.servers.iter().find(|s| s.is_unspecified() || s.is_broadcast()) {
+            return Err(format!("invalid relay server {}", server));
+        }
+        if self.max_hops == 0 || self.max_hops > Self::MAX_HOPS_LIMIT {
+            return Err(format!("max_hops must be between 1 and {}", Self::MAX_HOPS_LIMIT));
+        }
+        if self.interfaces.is_empty() {
+            return Err("relay has no interface".to_owned());
+        }
+        let mut names = HashSet::new();
+        let mut addresses = HashSet::new();
+        for iface in &self.interfaces {
+            if iface.name.is_empty() || iface.name.contains('\0') {
+                return Err(format!("invalid interface name `{}`", iface.name));
+            }
+            if !names.insert(&iface.name[..]) {
+                return Err(format!("relay interface {} is configured twice", iface.name));
+            }
+            if iface.address.is_unspecified() || iface.address.is_broadcast() {
+                return Err(format!("relay interface {}: invalid address {}", iface.name, iface.address));
+            }
+            if !addresses.insert(iface.address) {
+                return Err(format!("relay interface {}: address {} is used twice", iface.name, iface.address));
+            }
+        }
+        Ok(())
+    }
+}
+
+// A subnet is served to clients on the link of `interface`, and to clients
+// behind any relay agent whose giaddr falls inside `network`. Subnets
+// without an interface are only reachable through relays.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subnet {
+    pub network: Ipv4Net,
+    pub interface: Option<String>,
+    pub pool: Option<PoolRange>,
+    #[serde(default)]
+    pub exclude: Vec<Ipv4Addr>,
+    #[serde(default)]
+    pub options: OptionSet,
+    #[serde(default, rename = "host")]
+    pub hosts: Vec<Host>,
+    #[serde(default, rename = "agent_pool")]
+    pub agent_pools: Vec<AgentPool>,
+}
+
+// A fixed address for one client, recognized by its client identifier
+// (option 61), failing that its hardware address, and failing that the
+// circuit ID and remote ID a relay agent attached to its request (option
+// 82), every one given having to match. The address is never handed out
+// dynamically. `options` override those of the subnet.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Host {
+    pub hardware_address: Option<HexBytes>,
+    pub client_identifier: Option<HexBytes>,
+    pub circuit_id: Option<HexBytes>,
+    pub remote_id: Option<HexBytes>,
+    pub address: Ipv4Addr,
+    pub host_name: Option<String>,
+    pub next_server: Option<Ipv4Addr>,
+    pub boot_file: Option<String>,
+    #[serde(default)]
+    pub options: OptionSet,
+}
+
+// Addresses for the clients behind one relay agent port, recognized like
+// hosts by the circuit ID and remote ID the agent attaches. The range is
+// left out of the subnet's pool.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentPool {
+    pub circuit_id: Option<HexBytes>,
+    pub remote_id: Option<HexBytes>,
+    pub start: Ipv4Addr,
+    pub end: Ipv4Addr,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoolRange {
+    pub start: Ipv4Addr,
+    pub end: Ipv4Addr,
+}
+
+// Options handed to clients. The top-level set provides defaults for every
+// subnet; anything a subnet sets replaces the default.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OptionSet {
+    pub lease_time: Option<u32>,
+    pub routers: Option<Vec<Ipv4Addr>>,
+    pub domain_name_servers: Option<Vec<Ipv4Addr>>,
+    pub domain_name: Option<String>,
+    // Sent even to clients that do not ask for them in their parameter
+    // request list.
+    pub always_send: Option<Vec<OptionName>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OptionName {
+    SubnetMask,
+    Routers,
+    DomainNameServers,
+    DomainName,
+    HostName,
+}
+
+// How forgiving the server is of malformed requests. `lenient`, the
+// default, accepts requests shorter than 300 bytes, missing END, with
+// garbage after END or with options of the wrong length, which are then
+// ignored, as buggy clients send them; `strict` drops them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseMode {
+    Strict,
+    #[default]
+    Lenient,
+}
+
+impl ParseMode {
+    pub fn mode(self) -> Mode {
+        match self {
+            ParseMode::Strict => Mode::Strict,
+            ParseMode::Lenient => Mode::Lenient,
+        }
+    }
+}
+
+impl OptionName {
+    pub fn code(self) -> Code {
+        match self {
+            OptionName::SubnetMask => Code::SUBNET_MASK,
+            OptionName::Routers => Code::ROUTER,
+            OptionName::DomainNameServers => Code::DOMAIN_NAME_SERVER,
+            OptionName::DomainName => Code::DOMAIN_NAME,
+            OptionName::HostName => Code::HOST_NAME,
+        }
+    }
+}
+
+impl OptionSet {
+    pub fn merged(&self, overrides: &OptionSet) -> OptionSet {
+        OptionSet {
+            lease_time: overrides.lease_time.or(self.lease_time),
+            routers: overrides.routers.clone().or_else(|| self.routers.clone()),
+            domain_name_servers: overrides.domain_name_servers.clone().or_else(|| self.domain_name_servers.clone()),
+            domain_name: overrides.domain_name.clone().or_else(|| self.domain_name.clone()),
+            always_send: overrides.always_send.clone().or_else(|| self.always_send.clone()),
+        }
+    }
+}
+
+impl Config {
+    pub const DEFAULT_LEASE_TIME: u32 = 3600;
+    pub const DEFAULT_DECLINE_TIME: u32 = 3600;
+    pub const DEFAULT_PORT: u16 = 67;
+
+    fn default_decline_time() -> u32 {
+        Self::DEFAULT_DECLINE_TIME
+    }
+
+    fn default_port() -> u16 {
+        Self::DEFAULT_PORT
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn StdError>> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut config: Config = toml::from_str(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        for subnet in &mut config.subnets {
+            subnet.options = config.options.merged(&subnet.options);
+            for host in &mut subnet.hosts {
+                host.options = subnet.options.merged(&host.options);
+            }
+        }
+        config.validate()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.port == 0 || self.port == u16::MAX {
+            return Err(format!("invalid port {}", self.port));
+        }
+        if let Some(relay) = &self.relay {
+            if !self.interfaces.is_empty() || !self.subnets.is_empty() {
+                return Err("a relay agent serves no [[interface]] or [[subnet]]".to_owned());
+            }
+            return relay.validate();
+        }
+        if self.lease_file.as_os_str().is_empty() {
+            return Err("no lease_file configured".to_owned());
+        }
+        if self.interfaces.is_empty() {
+            return Err("no interface configured".to_owned());
+        }
+        let mut names = HashSet::new();
+        for iface in &self.interfaces {
+            if iface.name.is_empty() || iface.name.contains('\0') {
+                return Err(format!("invalid interface name `{}`", iface.name));
+            }
+            if !names.insert(&iface.name[..]) {
+                return Err(format!("interface {} is configured twice", iface.name));
+            }
+        }
+        if self.subnets.is_empty() {
+            return Err("no subnet configured".to_owned());
+        }
+        for (i, subnet) in self.subnets.iter().enumerate() {
+            self.validate_subnet(subnet)?;
+            if let Some(other) = self.subnets[..i].iter().find(|s| s.network.overlaps(&subnet.network)) {
+                return Err(format!("subnet {} overlaps subnet {}", subnet.network, other.network));
+            }
+        }
+        // Clients on the link must be able to reach us at the server
+        // identifier, so it has to be in one of the subnets there.
+        for iface in &self.interfaces {
+            let mut attached = self.subnets.iter()
+                .filter(|s| s.interface.as_deref() == Some(&iface.name[..]))
+                .peekable();
+            if attached.peek().is_some() && !attached.any(|s| s.network.contains_host(iface.server_identifier)) {
+                return Err(format!("interface {}: server_identifier {} is not a host address of any subnet on it", iface.name, iface.server_identifier));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_subnet(&self, subnet: &Subnet) -> Result<(), String> {
+        let net = subnet.network;
+        if net.prefix_len() > 30 {
+            return Err(format!("subnet {}: prefix is too long to hold any host", net));
+        }
+        if let Some(ref name) = subnet.interface {
+            if self.interface(name).is_none() {
+                return Err(format!("subnet {}: interface {} is not configured", net, name));
+            }
+        }
+        if let Some(pool) = subnet.pool {
+            for &addr in &[pool.start, pool.end] {
+                if !net.contains_host(addr) {
+                    return Err(format!("subnet {}: pool address {} is not a host address of the subnet", net, addr));
+                }
+            }
+            if u32::from(pool.start) > u32::from(pool.end) {
+                return Err(format!("subnet {}: pool start {} is after pool end {}", net, pool.start, pool.end));
+            }
+        }
+        validate_options(net, &subnet.options)
+            .map_err(|e| format!("subnet {}: {}", net, e))?;
+        let mut addrs = HashSet::new();
+        let mut keys = HashSet::new();
+        let mut agents = HashSet::new();
+        for host in &subnet.hosts {
+            self.validate_host(net, host)
+                .map_err(|e| format!("subnet {}: host {}: {}", net, host.address, e))?;
+            if !addrs.insert(host.address) {
+                return Err(format!("subnet {}: host {} is reserved twice", net, host.address));
+            }
+            let host_keys = host.client_identifier.iter().map(|id| (true, id))
+                .chain(host.hardware_address.iter().map(|hw| (false, hw)));
+            for (is_client_id, key) in host_keys {
+                if !keys.insert((is_client_id, key)) {
+                    return Err(format!("subnet {}: {} has more than one reservation", net, key));
+                }
+            }
+            if (host.circuit_id.is_some() || host.remote_id.is_some()) && !agents.insert((&host.circuit_id, &host.remote_id)) {
+                return Err(format!("subnet {}: {} has more than one reservation", net, agent_key(&host.circuit_id, &host.remote_id)));
+            }
+        }
+        let mut agents = HashSet::new();
+        for (i, pool) in subnet.agent_pools.iter().enumerate() {
+            let err = |e: String| format!("subnet {}: agent pool {}-{}: {}", net, pool.start, pool.end, e);
+            if pool.circuit_id.is_none() && pool.remote_id.is_none() {
+                return Err(err("neither circuit_id nor remote_id is given".to_owned()));
+            }
+            validate_agent_ids(&pool.circuit_id, &pool.remote_id).map_err(err)?;
+            for &addr in &[pool.start, pool.end] {
+                if !net.contains_host(addr) {
+                    return Err(err(format!("{} is not a host address of the subnet", addr)));
+                }
+            }
+            if u32::from(pool.start) > u32::from(pool.end) {
+                return Err(err("start is after end".to_owned()));
+            }
+            if let Some(other) = subnet.agent_pools[..i].iter().find(|p| u32::from(p.start) <= u32::from(pool.end) && u32::from(pool.start) <= u32::from(p.end)) {
+                return Err(err(format!("overlaps agent pool {}-{}", other.start, other.end)));
+            }
+            if !agents.insert((&pool.circuit_id, &pool.remote_id)) {
+                return Err(err(format!("{} has another agent pool", agent_key(&pool.circuit_id, &pool.remote_id))));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_host(&self, net: Ipv4Net, host: &Host) -> Result<(), String> {
+        if host.hardware_address.is_none() && host.client_identifier.is_none() && host.circuit_id.is_none() && host.remote_id.is_none() {
+            return Err("none of hardware_address, client_identifier, circuit_id and remote_id is given".to_owned());
+        }
+        if host.hardware_address.as_ref().is_some_and(|hw| hw.0.is_empty() || hw.0.len() > 16) {
+            return Err("hardware_address must be 1 to 16 bytes long".to_owned());
+        }
+        if host.client_identifier.as_ref().is_some_and(|id| id.0.len() < 2) {
+            return Err("client_identifier must be at least 2 bytes long".to_owned());
+        }
+        validate_agent_ids(&host.circuit_id, &host.remote_id)?;
+        if !net.contains_host(host.address) {
+            return Err("the address is not a host address of the subnet".to_owned());
+        }
+        if self.interfaces.iter().any(|iface| iface.server_identifier == host.address) {
+            return Err("the address belongs to this server".to_owned());
+        }
+        if let Some(ref host_name) = host.host_name {
+            if host_name.is_empty() || host_name.len() > 255 {
+                return Err("host_name must be 1 to 255 bytes long".to_owned());
+            }
+        }
+        if let Some(ref boot_file) = host.boot_file {
+            if boot_file.is_empty() || boot_file.len() > 127 {
+                return Err("boot_file must be 1 to 127 bytes long".to_owned());
+            }
+        }
+        validate_options(net, &host.options)
+    }
+
+    pub fn interface(&self, name: &str) -> Option<&Interface> {
+        self.interfaces.iter().find(|iface| iface.name == name)
+    }
+
+    // The pool of `subnet`, less the addresses of our own interfaces, the
+    // routers, reservations, agent pools and anything excluded explicitly.
+    pub fn build_pool(&self, subnet: &Subnet) -> Result<Pool, String> {
+        let mut pool = match subnet.pool {
+            Some(range) => Pool::new(range.start, range.end)?,
+            None => Pool::from_cidr(subnet.network.network(), subnet.network.prefix_len())?,
+        };
+        for agent_pool in &subnet.agent_pools {
+            pool.exclude_range(agent_pool.start, agent_pool.end);
+        }
+        self.exclude_reserved(subnet, &mut pool);
+        Ok(pool)
+    }
+
+    // Agent pool `index` of `subnet`, less what `build_pool` leaves out.
+    pub fn build_agent_pool(&self, subnet: &Subnet, index: usize) -> Result<Pool, String> {
+        let range = &subnet.agent_pools[index];
+        let mut pool = Pool::new(range.start, range.end)?;
+        self.exclude_reserved(subnet, &mut pool);
+        Ok(pool)
+    }
+
+    fn exclude_reserved(&self, subnet: &Subnet, pool: &mut Pool) {
+        for iface in &self.interfaces {
+            pool.exclude(iface.server_identifier);
+        }
+        for &addr in subnet.options.routers.iter().flatten().chain(&subnet.exclude) {
+            pool.exclude(addr);
+        }
+        for host in &subnet.hosts {
+            pool.exclude(host.address);
+        }
+    }
+}
+
+impl Subnet {
+    pub fn lease_time(&self) -> u32 {
+        self.options.lease_time.unwrap_or(Config::DEFAULT_LEASE_TIME)
+    }
+
+    // The reservation of the client with hardware address `chaddr` and
+    // client identifier `client_identifier`, whose request came with relay
+    // agent information `agent`. A reservation by client identifier wins
+    // over one by hardware address, which wins over one by agent.
+    pub fn host(&self, chaddr: &[u8], client_identifier: Option<&[u8]>, agent: Option<&RelayAgentInformation>) -> Option<&Host> {
+        client_identifier
+            .and_then(|id| self.hosts.iter().find(|h| h.client_identifier.as_ref().is_some_and(|i| i.0 == id)))
+            .or_else(|| self.hosts.iter().find(|h| h.hardware_address.as_ref().is_some_and(|hw| hw.0 == chaddr)))
+            .or_else(|| self.hosts.iter().find(|h| agent.is_some_and(|agent| agent_matches(&h.circuit_id, &h.remote_id, agent))))
+    }
+
+    // The index of the first agent pool for requests that came with relay
+    // agent information `agent`.
+    pub fn agent_pool(&self, agent: Option<&RelayAgentInformation>) -> Option<usize> {
+        let agent = agent?;
+        self.agent_pools.iter().position(|p| agent_matches(&p.circuit_id, &p.remote_id, agent))
+    }
+}
+
+impl Host {
+    pub fn lease_time(&self) -> u32 {
+        self.options.lease_time.unwrap_or(Config::DEFAULT_LEASE_TIME)
+    }
+}
+
+// Whether `agent` carries the circuit ID and remote ID given, at least one
+// of which must be.
+fn agent_matches(circuit_id: &Option<HexBytes>, remote_id: &Option<HexBytes>, agent: &RelayAgentInformation) -> bool {
+    (circuit_id.is_some() || remote_id.is_some())
+        && circuit_id.as_ref().is_none_or(|id| agent.circuit_id() == Some(&id.0[..]))
+        && remote_id.as_ref().is_none_or(|id| agent.remote_id() == Some(&id.0[..]))
+}
+
+fn agent_key(circuit_id: &Option<HexBytes>, remote_id: &Option<HexBytes>) -> String {
+    match (circuit_id, remote_id) {
+        (Some(circuit_id), Some(remote_id)) => format!("circuit_id {} with remote_id {}", circuit_id, remote_id),
+        (Some(circuit_id), None) => format!("circuit_id {}", circuit_id),
+        (None, Some(remote_id)) => format!("remote_id {}", remote_id),
+        (None, None) => "no agent".to_owned(),
+    }
+}
+
+fn validate_agent_ids(circuit_id: &Option<HexBytes>, remote_id: &Option<HexBytes>) -> Result<(), String> {
+    for (name, id) in &[("circuit_id", circuit_id), ("remote_id", remote_id)] {
+        if id.as_ref().is_some_and(|id| id.0.is_empty() || id.0.len() > RelayAgentInformation::MAX_SUB_OPTION_LEN) {
+            return Err(format!("{} must be 1 to {} bytes long", name, RelayAgentInformation::MAX_SUB_OPTION_LEN));
+        }
+    }
+    Ok(())
+}
+
+fn validate_options(net: Ipv4Net, options: &OptionSet) -> Result<(), String> {
+    for router in options.routers.iter().flatten() {
+        if !net.contains_host(*router) {
+            return Err(format!("router {} is not on the subnet", router));
+        }
+    }
+    if options.lease_time == Some(0) {
+        return Err("lease_time must be positive".to_owned());
+    }
+    if let Some(ref domain_name) = options.domain_name {
+        if domain_name.is_empty() || domain_name.len() > 255 {
+            return Err("domain_name must be 1 to 255 bytes long".to_owned());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(server_identifier: &str) -> Config {
+        toml::from_str(&format!(r#"
+            lease_file = "/tmp/leases"
+            [[interface]]
+            name = "eth0"
+            server_identifier = "{}"
+            [[subnet]]
+            network = "10.0.0.0/24"
+            interface = "eth0"
+            [[subnet]]
+            network = "10.0.1.0/24"
+            interface = "eth0"
+            [[subnet]]
+            network = "10.0.2.0/24"
+        "#, server_identifier)).unwrap()
+    }
+
+    #[test]
+    fn server_identifier_in_attached_subnet() {
+        assert!(config("10.0.0.1").validate().is_ok());
+        assert!(config("10.0.1.1").validate().is_ok());
+        // Only reachable through relays, so not on the link.
+        assert!(config("10.0.2.1").validate().is_err());
+        assert!(config("10.0.0.255").validate().is_err());
+        assert!(config("192.168.0.1").validate().is_err());
+    }
+
+    fn with_hosts(hosts: &str) -> Result<(), String> {
+        let config: Config = toml::from_str(&format!(r#"
+            lease_file = "/tmp/leases"
+            [[interface]]
+            name = "eth0"
+            server_identifier = "10.0.0.1"
+            [[subnet]]
+            network = "10.0.0.0/24"
+            interface = "eth0"
+            {}
+        "#, hosts)).unwrap();
+        config.validate()
+    }
+
+    #[test]
+    fn reservations_are_checked() {
+        let host = |key: &str, address: &str| format!("[[subnet.host]]\n{}\naddress = \"{}\"\n", key, address);
+        let hw = |n: u8| format!("hardware_address = \"02:00:00:00:00:{:02x}\"", n);
+        assert!(with_hosts(&(host(&hw(1), "10.0.0.20") + &host(&hw(2), "10.0.0.21"))).is_ok());
+        let err = with_hosts(&(host(&hw(1), "10.0.0.20") + &host(&hw(2), "10.0.0.20"))).unwrap_err();
+        assert!(err.contains("reserved twice"), "{}", err);
+        let err = with_hosts(&(host(&hw(1), "10.0.0.20") + &host(&hw(1), "10.0.0.21"))).unwrap_err();
+        assert!(err.contains("more than one reservation"), "{}", err);
+        let id = "client_identifier = \"01:02:00:00:00:00:01\"";
+        let err = with_hosts(&(host(id, "10.0.0.20") + &host(id, "10.0.0.21"))).unwrap_err();
+        assert!(err.contains("more than one reservation"), "{}", err);
+        // The same bytes as a client identifier and a hardware address are
+        // different keys.
+        assert!(with_hosts(&(host("client_identifier = \"02:00:00:00:00:01\"", "10.0.0.20") + &host(&hw(1), "10.0.0.21"))).is_ok());
+        for address in &["10.0.1.20", "10.0.0.0", "10.0.0.255"] {
+            let err = with_hosts(&host(&hw(1), address)).unwrap_err();
+            assert!(err.contains("not a host address of the subnet"), "{}", err);
+        }
+        let err = with_hosts(&host(&hw(1), "10.0.0.1")).unwrap_err();
+        assert!(err.contains("belongs to this server"), "{}", err);
+        let err = with_hosts(&host("host_name = \"x\"", "10.0.0.20")).unwrap_err();
+        assert!(err.contains("none of"), "{}", err);
+        assert!(with_hosts(&host("client_identifier = \"01\"", "10.0.0.20")).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::net::SocketAddr;
+
+use dhcpv4::{message, Message, OpCode};
+use dhcpv4::message::{Header, TooLarge};
+use dhcpv4::option::Code;
+use dhcpv4::options::OptionSet;
+use dhcpv4::options::bytes::AddBytesExt;
+use dhcpv4::options::end::AddEndExt;
+use dhcpv4::options::message_type::{AddMessageTypeExt, GetMessageTypeExt, MessageType};
+
+use super::pool::ClientId;
+
+pub struct Request<'a> {
+    // The name of the interface the request arrived on.
+    pub interface: &'a str,
+    pub peer: SocketAddr,
+    pub message: &'a Message<&'a [u8]>,
+    pub options: &'a OptionSet,
+    pub message_type: MessageType,
+    pub client: ClientId,
+}
+
+impl<'a> Request<'a> {
+    pub fn header(&self) -> Header<&'a [u8]> {
+        Header::<&_>::new(&self.message.as_slice()[..Header::<()>::SIZE]).unwrap()
+    }
+}
+
+// A reply that is still being drafted. The header lives in a
+// `message::Builder`; options are kept aside in an `OptionSet` so that
+// handlers can replace or remove them before the packet is serialized.
+// Adding an option whose code is already present replaces the old value.
+pub struct Reply {
+    builder: message::Builder,
+    options: OptionSet,
+}
+
+impl Reply {
+    pub fn new(requ_hdr: &Header<&[u8]>, message_type: MessageType) -> Self {
+        let mut builder = message::Builder::new();
+        {
+            let mut repl_hdr = builder.header_mut();
+            repl_hdr.set_op_code(OpCode::BOOTREPLY);
+            repl_hdr.set_htype(requ_hdr.htype());
+            repl_hdr.set_hlen(requ_hdr.hlen());
+            repl_hdr.set_xid(requ_hdr.xid());
+            repl_hdr.set_flags(requ_hdr.flags());
+            repl_hdr.set_giaddr(requ_hdr.giaddr());
+            repl_hdr.chaddr().copy_from_slice(requ_hdr.chaddr());
+        }
+        let mut reply = Self {
+            builder,
+            options: OptionSet::new(),
+        };
+        reply.add_message_type(message_type);
+        reply
+    }
+
+    pub fn header(&self) -> Header<&[u8]> {
+        self.builder.header()
+    }
+
+    pub fn header_mut(&mut self) -> Header<&mut [u8]> {
+        self.builder.header_mut()
+    }
+
+    pub fn message_type(&self) -> Option<MessageType> {
+        self.options.get_message_type()
+    }
+
+    pub fn option(&self, code: Code) -> Option<&[u8]> {
+        self.options.get(code)
+    }
+
+    pub fn options(&self) -> &OptionSet {
+        &self.options
+    }
+
+    pub fn remove_option(&mut self, code: Code) {
+        self.retain_options(|c| c != code);
+    }
+
+    // Removes every option for which `f` returns false, except the message
+    // type.
+    pub fn retain_options<F: FnMut(Code) -> bool>(&mut self, mut f: F) {
+        self.options.retain(|c, _| c == Code::DHCP_MESSAGE_TYPE || f(c));
+    }
+
+    // Reorders the options by `f`; options with equal keys keep their
+    // relative order.
+    pub fn sort_options_by_key<K: Ord, F: FnMut(Code) -> K>(&mut self, f: F) {
+        self.options.sort_by_key(f);
+    }
+
+    // Adds the options of `defaults` that the reply does not carry yet.
+    pub fn merge_options(&mut self, defaults: &OptionSet) {
+        self.options.merge(defaults);
+    }
+
+    // Limits the reply to what the client says it accepts.
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.builder.set_max_size(max_size);
+    }
+
+    pub fn finish(mut self) -> Result<Vec<u8>, TooLarge> {
+        {
+            let mut opts_bldr = self.builder.options_builder();
+            opts_bldr.add_magic_cookie();
+            self.options.write(&mut opts_bldr);
+            opts_bldr.add_end();
+        }
+        self.builder.finish_owned()
+    }
+}
+
+impl AddBytesExt for Reply {
+    fn add_bytes(&mut self, code: Code, bytes: &[u8]) {
+        self.options.add_bytes(code, bytes);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Continue,
+    Drop,
+}
+
+// A hook into request processing. `request` runs for every well-formed
+// request before the server acts on it; `reply` runs once the server has
+// drafted its answer and may rewrite it. Returning `Verdict::Drop` from
+// either stops the chain and discards the message.
+pub trait Handler: Send {
+    fn request(&mut self, _request: &Request<'_>) -> Verdict {
+        Verdict::Continue
+    }
+
+    fn reply(&mut self, _request: &Request<'_>, _reply: &mut Reply) -> Verdict {
+        Verdict::Continue
+    }
+}
+
+#[derive(Default)]
+pub struct Chain(Vec<Box<dyn Handler>>);
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<H: Handler + 'static>(&mut self, handler: H) -> &mut Self {
+        self.0.push(Box::new(handler));
+        self
+    }
+
+    pub fn request(&mut self, request: &Request<'_>) -> Verdict {
+        for handler in &mut self.0 {
+            if handler.request(request) == Verdict::Drop {
+                return Verdict::Drop;
+            }
+        }
+        Verdict::Continue
+    }
+
+    pub fn reply(&mut self, request: &Request<'_>, reply: &mut Reply) -> Verdict {
+        for handler in &mut self.0 {
+            if handler.reply(request, reply) == Verdict::Drop {
+                return Verdict::Drop;
+            }
+        }
+        Verdict::Continue
+    }
+}
+
+pub struct Log;
+
+impl Handler for Log {
+    fn request(&mut self, request: &Request<'_>) -> Verdict {
+        println!("=> {:?} from {:02x?} on {}", request.message_type, request.client.0, request.interface);
+        Verdict::Continue
+    }
+
+    fn reply(&mut self, _request: &Request<'_>, reply: &mut Reply) -> Verdict {
+        println!("<= {:?} {}", reply.message_type(), reply.header().yiaddr());
+        Verdict::Continue
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::pool::ClientId;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaseState {
+    Offered,
+    Bound,
+    // Reported by a client as already in use by somebody else; nobody gets
+    // the address until the lease expires.
+    Declined,
+}
+
+impl LeaseState {
+    fn as_str(self) -> &'static str {
+        match self {
+            LeaseState::Offered => "offered",
+            LeaseState::Bound => "bound",
+            LeaseState::Declined => "declined",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "offered" => Some(LeaseState::Offered),
+            "bound" => Some(LeaseState::Bound),
+            "declined" => Some(LeaseState::Declined),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub client: ClientId,
+    pub addr: Ipv4Addr,
+    pub start: u64,
+    pub expiry: u64,
+    pub state: LeaseState,
+    pub hostname: Option<Vec<u8>>,
+}
+
+impl Lease {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expiry <= now
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Leases are kept in a single append-only journal, one record per line:
+//
+//   lease <addr> <client> <start> <expiry> <state> <hostname>
+//   free <addr>
+//
+// where <client> and <hostname> are hex encoded ("-" for no hostname). The
+// journal is rewritten with only the live leases once it has grown well
+// past them; the rewrite goes to a temporary file which is renamed over the
+// journal, so a crash leaves either the old or the new journal in place. A
+// torn record at the tail is ignored on reload.
+pub struct LeaseStore {
+    path: PathBuf,
+    journal: File,
+    leases: HashMap<Ipv4Addr, Lease>,
+    by_client: HashMap<ClientId, Ipv4Addr>,
+    records: usize,
+}
+
+impl LeaseStore {
+    const COMPACT_MIN_RECORDS: usize = 1024;
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut leases = HashMap::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for (lineno, line) in BufReader::new(file).split(b'\n').enumerate() {
+                    let line = line?;
+                    match std::str::from_utf8(&line).ok().and_then(parse_record) {
+                        Some(Record::Lease(lease)) => {
+                            leases.insert(lease.addr, lease);
+                        },
+                        Some(Record::Free(addr)) => {
+                            leases.remove(&addr);
+                        },
+                        None => eprintln!("{}:{}: ignoring malformed lease record", path.display(), lineno + 1),
+                    }
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+        let by_client = leases.values()
+            .filter(|l| l.state != LeaseState::Declined)
+            .map(|l| (l.client.clone(), l.addr))
+            .collect();
+        let journal = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut store = Self {
+            path,
+            journal,
+            leases,
+            by_client,
+            records: 0,
+        };
+        store.compact()?;
+        Ok(store)
+    }
+
+    pub fn leases(&self) -> impl Iterator<Item = &Lease> {
+        self.leases.values()
+    }
+
+    pub fn get(&self, addr: Ipv4Addr) -> Option<&Lease> {
+        self.leases.get(&addr)
+    }
+
+    pub fn get_by_client(&self, client: &ClientId) -> Option<&Lease> {
+        self.by_client.get(client).and_then(|addr| self.leases.get(addr))
+    }
+
+    // A client holds at most one lease, so inserting a lease drops whatever
+    // else the client held. Declined leases belong to nobody and are exempt.
+    pub fn insert(&mut self, lease: Lease) -> io::Result<()> {
+        let declined = lease.state == LeaseState::Declined;
+        if let Some(&old) = self.by_client.get(&lease.client) {
+            if old != lease.addr && !declined {
+                self.remove(old)?;
+            }
+        }
+        self.append(&format_lease(&lease))?;
+        let (client, addr) = (lease.client.clone(), lease.addr);
+        if let Some(old) = self.leases.insert(addr, lease) {
+            if self.by_client.get(&old.client) == Some(&addr) {
+                self.by_client.remove(&old.client);
+            }
+        }
+        if !declined {
+            self.by_client.insert(client, addr);
+        }
+        self.maybe_compact()
+    }
+
+    pub fn remove(&mut self, addr: Ipv4Addr) -> io::Result<Option<Lease>> {
+        if !self.leases.contains_key(&addr) {
+            return Ok(None);
+        }
+        self.append(&format!("free {}\n", addr))?;
+        let lease = self.leases.remove(&addr);
+        if let Some(ref lease) = lease {
+            if self.by_client.get(&lease.client) == Some(&addr) {
+                self.by_client.remove(&lease.client);
+            }
+        }
+        self.maybe_compact()?;
+        Ok(lease)
+    }
+
+    pub fn expire(&mut self, now: u64) -> io::Result<Vec<Lease>> {
+        let expired: Vec<_> = self.leases.values()
+            .filter(|l| l.is_expired(now))
+            .map(|l| l.addr)
+            .collect();
+        let mut leases = Vec::with_capacity(expired.len());
+        for addr in expired {
+            leases.extend(self.remove(addr)?);
+        }
+        Ok(leases)
+    }
+
+    pub fn compact(&mut self) -> io::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            let mut buf = String::new();
+            for lease in self.leases.values() {
+                buf.push_str(&format_lease(lease));
+            }
+            tmp.write_all(buf.as_bytes())?;
+            tmp.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            File::open(dir)?.sync_all()?;
+        }
+        self.journal = OpenOptions::new().append(true).open(&self.path)?;
+        self.records = self.leases.len();
+        Ok(())
+    }
+
+    fn append(&mut self, record: &str) -> io::Result<()> {
+        self.journal.write_all(record.as_bytes())?;
+        self.journal.sync_data()?;
+        self.records += 1;
+        Ok(())
+    }
+
+    fn maybe_compact(&mut self) -> io::Result<()> {
+        if self.records > Self::COMPACT_MIN_RECORDS && self.records > self.leases.len() * 2 {
+            self.compact()?;
+        }
+        Ok(())
+    }
+}
+
+enum Record {
+    Lease(Lease),
+    Free(Ipv4Addr),
+}
+
+fn parse_record(line: &str) -> Option<Record> {
+    let mut fields = line.split(' ');
+    let record = match fields.next()? {
+        "lease" => Record::Lease(Lease {
+            addr: fields.next()?.parse().ok()?,
+            client: ClientId(from_hex(fields.next()?)?),
+            start: fields.next()?.parse().ok()?,
+            expiry: fields.next()?.parse().ok()?,
+            state: LeaseState::parse(fields.next()?)?,
+            hostname: match fields.next()? {
+                "-" => None,
+                hex => Some(from_hex(hex)?),
+            },
+        }),
+        "free" => Record::Free(fields.next()?.parse().ok()?),
+        _ => return None,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(record)
+}
+
+fn format_lease(lease: &Lease) -> String {
+    format!(
+        "lease {} {} {} {} {} {}\n",
+        lease.addr,
+        to_hex(&lease.client.0),
+        lease.start,
+        lease.expiry,
+        lease.state.as_str(),
+        lease.hostname.as_deref().map_or_else(|| "-".to_owned(), to_hex),
+    )
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(s, "{:02x}", b).unwrap();
+    }
+    s
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // A journal path of its own for each test, removed when dropped.
+    struct TempJournal(PathBuf);
+
+    impl TempJournal {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let name = format!("bhcq-lease-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::SeqCst));
+            let path = std::env::temp_dir().join(name);
+            let _ = fs::remove_file(&path);
+            TempJournal(path)
+        }
+
+        fn lines(&self) -> usize {
+            fs::read_to_string(&self.0).unwrap().lines().count()
+        }
+    }
+
+    impl Drop for TempJournal {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn lease(client: u8, addr: [u8; 4], state: LeaseState) -> Lease {
+        Lease {
+            client: ClientId(vec![1, 2, 3, 4, 5, client]),
+            addr: addr.into(),
+            start: 100,
+            expiry: 200,
+            state,
+            hostname: Some(b"host".to_vec()),
+        }
+    }
+
+    #[test]
+    fn replays_journal_on_open() {
+        let journal = TempJournal::new();
+        let kept = lease(1, [10, 0, 0, 1], LeaseState::Bound);
+        let mut store = LeaseStore::open(&journal.0).unwrap();
+        store.insert(kept.clone()).unwrap();
+        store.insert(lease(2, [10, 0, 0, 2], LeaseState::Offered)).unwrap();
+        store.remove([10, 0, 0, 2].into()).unwrap();
+        drop(store);
+
+        let store = LeaseStore::open(&journal.0).unwrap();
+        assert_eq!(store.leases().collect::<Vec<_>>(), vec![&kept]);
+        assert_eq!(store.get_by_client(&kept.client), Some(&kept));
+    }
+
+    #[test]
+    fn client_holds_one_lease() {
+        let journal = TempJournal::new();
+        let mut store = LeaseStore::open(&journal.0).unwrap();
+        store.insert(lease(1, [10, 0, 0, 1], LeaseState::Bound)).unwrap();
+        store.insert(lease(1, [10, 0, 0, 2], LeaseState::Bound)).unwrap();
+        assert_eq!(store.leases().count(), 1);
+        assert_eq!(store.get_by_client(&lease(1, [0; 4], LeaseState::Bound).client).unwrap().addr, Ipv4Addr::new(10, 0, 0, 2));
+    }
+
+    #[test]
+    fn declined_lease_belongs_to_nobody() {
+        let journal = TempJournal::new();
+        let mut store = LeaseStore::open(&journal.0).unwrap();
+        let bound = lease(1, [10, 0, 0, 1], LeaseState::Bound);
+        store.insert(bound.clone()).unwrap();
+        store.insert(lease(1, [10, 0, 0, 2], LeaseState::Declined)).unwrap();
+        assert_eq!(store.leases().count(), 2);
+        assert_eq!(store.get_by_client(&bound.client), Some(&bound));
+    }
+
+    #[test]
+    fn expire_frees_expired_leases() {
+        let journal = TempJournal::new();
+        let mut store = LeaseStore::open(&journal.0).unwrap();
+        let mut live = lease(1, [10, 0, 0, 1], LeaseState::Bound);
+        live.expiry = 300;
+        store.insert(live.clone()).unwrap();
+        store.insert(lease(2, [10, 0, 0, 2], LeaseState::Bound)).unwrap();
+        let expired = store.expire(200).unwrap();
+        assert_eq!(expired.iter().map(|l| l.addr).collect::<Vec<_>>(), vec![Ipv4Addr::new(10, 0, 0, 2)]);
+        assert_eq!(store.leases().collect::<Vec<_>>(), vec![&live]);
+    }
+
+    #[test]
+    fn ignores_torn_tail_record() {
+        let journal = TempJournal::new();
+        let kept = lease(1, [10, 0, 0, 1], LeaseState::Bound);
+        let mut store = LeaseStore::open(&journal.0).unwrap();
+        store.insert(kept.clone()).unwrap();
+        drop(store);
+        // A crash in the middle of appending a record.
+        let mut file = OpenOptions::new().append(true).open(&journal.0).unwrap();
+        file.write_all(b"lease 10.0.0.2 0102030405").unwrap();
+        drop(file);
+
+        let store = LeaseStore::open(&journal.0).unwrap();
+        assert_eq!(store.leases().collect::<Vec<_>>(), vec![&kept]);
+    }
+
+    #[test]
+    fn compacts_journal_and_reloads_it() {
+        let journal = TempJournal::new();
+        let mut store = LeaseStore::open(&journal.0).unwrap();
+        for i in 0..=LeaseStore::COMPACT_MIN_RECORDS {
+            let mut l = lease(1, [10, 0, (i / 250) as u8, (i % 250) as u8 + 1], LeaseState::Bound);
+            l.start = i as u64;
+            store.insert(l).unwrap();
+        }
+        // Every insert replaced the client's previous lease, so one is left.
+        assert_eq!(store.leases().count(), 1);
+        assert!(journal.lines() < LeaseStore::COMPACT_MIN_RECORDS);
+        let last = store.leases().next().unwrap().clone();
+        drop(store);
+
+        let store = LeaseStore::open(&journal.0).unwrap();
+        assert_eq!(store.leases().collect::<Vec<_>>(), vec![&last]);
+        assert_eq!(journal.lines(), 1);
+    }
+
+    #[test]
+    fn survives_crash_during_compaction() {
+        let journal = TempJournal::new();
+        let kept = lease(1, [10, 0, 0, 1], LeaseState::Bound);
+        let mut store = LeaseStore::open(&journal.0).unwrap();
+        store.insert(kept.clone()).unwrap();
+        drop(store);
+        // A rewrite that never got renamed over the journal.
+        let mut tmp_path = journal.0.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, "lease 10.0.0.9 01 0 1 bound -\n").unwrap();
+
+        let store = LeaseStore::open(&journal.0).unwrap();
+        assert_eq!(store.leases().collect::<Vec<_>>(), vec![&kept]);
+        assert!(!Path::new(&tmp_path).exists());
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(from_hex(&to_hex(&[0, 0xab, 0xff])), Some(vec![0, 0xab, 0xff]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
@@ -0,0 +1,9 @@
+pub mod pool;
+pub mod lease;
+pub mod config;
+pub mod handler;
+pub mod raw;
+pub mod server;
+pub mod relay;
+pub use server::Server;
+pub use relay::Relay;
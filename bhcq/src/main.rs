@@ -1,159 +1,54 @@
-use libc;
-use nix::{errno::Errno, sys::socket::{self, AddressFamily, SockFlag, SockProtocol, SockType, sockopt, SockAddr}};
 use std::ffi::CString;
-use std::os::unix::io::FromRawFd;
-use std::net;
 use std::error::Error as StdError;
-use std::iter::FromIterator;
-use std::collections::BTreeMap;
-use tokio::net::UdpSocket;
-use tokio::prelude::*;
-use dhcpv4::{self, message, Message, OpCode};
-use dhcpv4::options::{
-    message_type::*,
-    subnet_mask::*,
-    routers::*,
-    lease_time::*,
-    domain_name_servers::*,
-    requested_ip_address::*,
-    server_identifier::*,
-    end::*,
-};
+use bhcq::{server, Relay, Server};
+use bhcq::config::Config;
+use bhcq::handler::Log;
+use bhcq::raw::RawSender;
+
+const DEFAULT_CONFIG_FILE: &str = "/etc/bhcq.toml";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn StdError>> {
-    let sock = bind(CString::new("ens4").unwrap()).unwrap();
-    do_loop(sock).await
-}
-
-fn bind(ifname: CString) -> Result<UdpSocket, Box<dyn StdError>> {
-    let fd = socket::socket(
-        AddressFamily::Inet,
-        SockType::Datagram,
-        SockFlag::empty(),
-        Some(SockProtocol::Udp),
-    )?;
-    let addr: net::SocketAddr = net::SocketAddrV4::new(net::Ipv4Addr::new(0, 0, 0, 0), 67).into();
-    socket::bind(fd, &SockAddr::Inet(socket::InetAddr::from_std(&addr)))?;
-    socket::setsockopt(fd, sockopt::ReuseAddr, &true)?;
-    unsafe {
-        let res = libc::setsockopt(
-            fd,
-            libc::SOL_SOCKET,
-            libc::SO_BINDTODEVICE,
-            ifname.as_ptr() as *const libc::c_void,
-            ifname.as_bytes().len() as u32,
-        );
-        Errno::result(res).map(drop)
-    }?;
-    let std_sock = unsafe { net::UdpSocket::from_raw_fd(fd) };
-    let sock = UdpSocket::from_std(std_sock)?;
-    sock.set_broadcast(true)?;
-    Ok(sock)
+    let mut args = std::env::args().skip(1);
+    let config_file = args.next().unwrap_or_else(|| DEFAULT_CONFIG_FILE.to_owned());
+    if args.next().is_some() {
+        return Err("usage: bhcq [CONFIG_FILE]".into());
+    }
+    let config = Config::load(&config_file)?;
+    if config.relay.is_some() {
+        return run_relay(config).await;
+    }
+    let mut builder = Server::builder(config.clone());
+    for iface in &config.interfaces {
+        let sock = server::bind(CString::new(iface.name.clone())?, config.port)
+            .map_err(|e| format!("{}: {}", iface.name, e))?;
+        builder = builder.socket(&iface.name, sock);
+        if iface.raw_socket {
+            let raw = RawSender::open(&iface.name)
+                .map_err(|e| format!("{}: raw socket: {}", iface.name, e))?;
+            builder = builder.raw_sender(&iface.name, raw);
+        }
+    }
+    let mut server = builder.handler(Log).build()?;
+    server.run().await
 }
 
-async fn do_loop(mut sock: UdpSocket) -> Result<(), Box<dyn StdError>> {
-    let mut buf = vec![0u8; 4096];
-    let bcast_sock_addr = net::SocketAddr::new(net::Ipv4Addr::BROADCAST.into(), 68);
-    loop {
-        let (read, peer) = sock.recv_from(&mut buf).await?;
-        let bytes = &buf[0..read];
-        let m = Message::new(bytes).ok_or("malformed size packet")?;
-        let requ_hdr = m.header();
-        let opts = m.options();
-        let opts_iter = opts.try_iter().ok_or("malformed magic cookie")?;
-        let opts_map = BTreeMap::from_iter(opts_iter.filter_map(Into::into));
-        let message_type = opts_map.get_message_type().ok_or("no message type")?;
-        println!("{:?}", message_type);
-        match message_type {
-            MessageType::DHCPDISCOVER => {
-                println!("=> DISCOVER");
-                let mut bldr = message::Builder::new();
-                {
-                    let mut repl_hdr = bldr.header_mut();
-                    repl_hdr.set_op_code(OpCode::BOOTREPLY);
-                    repl_hdr.set_xid(requ_hdr.xid());
-                    repl_hdr.set_flags(requ_hdr.flags());
-                    repl_hdr.set_yiaddr(net::Ipv4Addr::new(192, 168, 44, 2));
-                    repl_hdr.set_giaddr(requ_hdr.giaddr());
-                    repl_hdr.chaddr().copy_from_slice(requ_hdr.chaddr());
-                }
-                {
-                    let mut opts_bldr = bldr.options_builder();
-                    opts_bldr.add_magic_cookie();
-                    opts_bldr.add_message_type(MessageType::DHCPOFFER);
-                    opts_bldr.add_subnet_mask(net::Ipv4Addr::new(255, 255, 255, 0));
-                    opts_bldr.add_routers(&[net::Ipv4Addr::new(192, 168, 44, 1)]);
-                    opts_bldr.add_lease_time(30);
-                    opts_bldr.add_server_identifier(net::Ipv4Addr::new(192, 168, 44, 1));
-                    opts_bldr.add_domain_name_servers(&[
-                        net::Ipv4Addr::new(8, 8, 8, 8),
-                        net::Ipv4Addr::new(8, 8, 4, 4),
-                    ]);
-                    opts_bldr.add_end();
-                }
-                let packet = bldr.finish();
-                sock.send_to(packet, bcast_sock_addr).await?;
-                println!("<= OFFER");
-            },
-            MessageType::DHCPREQUEST => {
-                println!("=> REQUEST");
-                //println!("{:?}", opts_map);
-                let req_ip = match opts_map.get_requested_ip_address() {
-                    Some(req_ip) => req_ip,
-                    None => requ_hdr.ciaddr(),
-                };
-                let mut bldr = message::Builder::new();
-                if req_ip == net::Ipv4Addr::new(192, 168, 44, 2) {
-                    {
-                        let mut repl_hdr = bldr.header_mut();
-                        repl_hdr.set_op_code(OpCode::BOOTREPLY);
-                        repl_hdr.set_xid(requ_hdr.xid());
-                        repl_hdr.set_flags(requ_hdr.flags());
-                        repl_hdr.set_ciaddr(requ_hdr.ciaddr());
-                        repl_hdr.set_yiaddr(net::Ipv4Addr::new(192, 168, 44, 2));
-                        repl_hdr.set_giaddr(requ_hdr.giaddr());
-                        repl_hdr.chaddr().copy_from_slice(requ_hdr.chaddr());
-                    }
-                    {
-                        let mut opts_bldr = bldr.options_builder();
-                        opts_bldr.add_magic_cookie();
-                        opts_bldr.add_message_type(MessageType::DHCPACK);
-                        opts_bldr.add_subnet_mask(net::Ipv4Addr::new(255, 255, 255, 0));
-                        opts_bldr.add_routers(&[net::Ipv4Addr::new(192, 168, 44, 1)]);
-                        opts_bldr.add_lease_time(30);
-                        opts_bldr.add_server_identifier(net::Ipv4Addr::new(192, 168, 44, 1));
-                        opts_bldr.add_domain_name_servers(&[
-                            net::Ipv4Addr::new(8, 8, 8, 8),
-                            net::Ipv4Addr::new(8, 8, 4, 4),
-                        ]);
-                        opts_bldr.add_end();
-                    }
-                } else {
-                    {
-                        let mut repl_hdr = bldr.header_mut();
-                        repl_hdr.set_op_code(OpCode::BOOTREPLY);
-                        repl_hdr.set_xid(requ_hdr.xid());
-                        repl_hdr.set_flags(requ_hdr.flags());
-                        repl_hdr.set_giaddr(requ_hdr.giaddr());
-                        repl_hdr.chaddr().copy_from_slice(requ_hdr.chaddr());
-                    }
-                    {
-                        let mut opts_bldr = bldr.options_builder();
-                        opts_bldr.add_magic_cookie();
-                        opts_bldr.add_message_type(MessageType::DHCPNAK);
-                        opts_bldr.add_server_identifier(net::Ipv4Addr::new(192, 168, 44, 1));
-                        opts_bldr.add_end();
-                    }
-                }
-                let packet = bldr.finish();
-                //println!("{:02x?}", packet);
-                sock.send_to(packet, bcast_sock_addr).await?;
-                println!("<= ACK");
-            },
-            _ => {
-
-            },
+async fn run_relay(config: Config) -> Result<(), Box<dyn StdError>> {
+    let mut builder = Relay::builder(config.clone());
+    if let Some(relay) = &config.relay {
+        for iface in &relay.interfaces {
+            let sock = server::bind(CString::new(iface.name.clone())?, config.port)
+                .map_err(|e| format!("{}: {}", iface.name, e))?;
+            builder = builder.socket(&iface.name, sock);
+            if iface.raw_socket {
+                let raw = RawSender::open(&iface.name)
+                    .map_err(|e| format!("{}: raw socket: {}", iface.name, e))?;
+                builder = builder.raw_sender(&iface.name, raw);
+            }
         }
     }
+    let upstream = server::bind_any(config.port)
+        .map_err(|e| format!("upstream: {}", e))?;
+    let mut relay = builder.upstream_socket(upstream).build()?;
+    relay.run().await
 }
@@ -0,0 +1,299 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::Ipv4Addr;
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClientId(pub Vec<u8>);
+
+impl ClientId {
+    pub fn from_chaddr(hlen: u8, chaddr: &[u8]) -> Self {
+        let len = (hlen as usize).min(chaddr.len());
+        ClientId(chaddr[..len].to_vec())
+    }
+
+    // Clients are told apart by their client identifier (option 61) if they
+    // send one, and by their hardware address otherwise (RFC 2131, section
+    // 4.2).
+    pub fn from_request(hlen: u8, chaddr: &[u8], client_identifier: Option<&[u8]>) -> Self {
+        match client_identifier {
+            Some(id) => ClientId(id.to_vec()),
+            None => Self::from_chaddr(hlen, chaddr),
+        }
+    }
+}
+
+pub trait Allocator {
+    // Picks an address for `client` and holds it until released. The
+    // client's current address wins over `requested`, which wins over any
+    // other free address.
+    fn offer(&mut self, client: &ClientId, requested: Option<Ipv4Addr>) -> Option<Ipv4Addr>;
+
+    // Binds `addr` to `client`. Fails if the address is not ours to give or
+    // is already bound to somebody else.
+    fn allocate(&mut self, client: &ClientId, addr: Ipv4Addr) -> bool;
+
+    fn release(&mut self, client: &ClientId, addr: Ipv4Addr);
+
+    // Takes `addr` out of circulation, whoever holds it, until it is
+    // unquarantined.
+    fn quarantine(&mut self, addr: Ipv4Addr);
+
+    fn unquarantine(&mut self, addr: Ipv4Addr);
+}
+
+pub struct Pool {
+    start: u32,
+    end: u32,
+    excluded: BTreeSet<u32>,
+    // Inclusive ranges left out as a whole, such as agent pools.
+    excluded_ranges: Vec<(u32, u32)>,
+    bindings: HashMap<u32, ClientId>,
+    by_client: HashMap<ClientId, u32>,
+    quarantined: HashSet<u32>,
+    cursor: u32,
+}
+
+impl Pool {
+    pub fn new(start: Ipv4Addr, end: Ipv4Addr) -> Result<Self, String> {
+        if u32::from(start) > u32::from(end) {
+            return Err(format!("pool start {} is after pool end {}", start, end));
+        }
+        let (start, end) = (u32::from(start), u32::from(end));
+        Ok(Self {
+            start,
+            end,
+            excluded: BTreeSet::new(),
+            excluded_ranges: Vec::new(),
+            bindings: HashMap::new(),
+            by_client: HashMap::new(),
+            quarantined: HashSet::new(),
+            cursor: start,
+        })
+    }
+
+    // Every host address of `network/prefix_len`; the network and broadcast
+    // addresses are left out.
+    pub fn from_cidr(network: Ipv4Addr, prefix_len: u8) -> Result<Self, String> {
+        if prefix_len > 30 {
+            return Err(format!("prefix /{} is too long to hold any host", prefix_len));
+        }
+        let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+        let network = u32::from(network) & mask;
+        let broadcast = network | !mask;
+        Self::new(Ipv4Addr::from(network + 1), Ipv4Addr::from(broadcast - 1))
+    }
+
+    pub fn exclude(&mut self, addr: Ipv4Addr) -> &mut Self {
+        self.excluded.insert(addr.into());
+        self
+    }
+
+    // Leaves out `start` through `end`, without going through them one by
+    // one.
+    pub fn exclude_range(&mut self, start: Ipv4Addr, end: Ipv4Addr) -> &mut Self {
+        self.excluded_ranges.push((start.into(), end.into()));
+        self
+    }
+
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        let addr = u32::from(addr);
+        self.start <= addr && addr <= self.end
+            && !self.excluded.contains(&addr)
+            && !self.excluded_ranges.iter().any(|&(start, end)| start <= addr && addr <= end)
+    }
+
+    fn is_free(&self, addr: u32) -> bool {
+        self.contains(addr.into()) && !self.bindings.contains_key(&addr) && !self.quarantined.contains(&addr)
+    }
+
+    fn bind(&mut self, client: &ClientId, addr: u32) {
+        if let Some(old) = self.by_client.insert(client.clone(), addr) {
+            if old != addr {
+                self.bindings.remove(&old);
+            }
+        }
+        self.bindings.insert(addr, client.clone());
+    }
+
+    fn next_free(&mut self) -> Option<u32> {
+        let size = u64::from(self.end - self.start) + 1;
+        let offset = u64::from(self.cursor - self.start);
+        let mut i = 0;
+        while i < size {
+            let addr = self.start + ((offset + i) % size) as u32;
+            // Step over an excluded range in one go.
+            if let Some(&(_, end)) = self.excluded_ranges.iter().find(|&&(start, end)| start <= addr && addr <= end) {
+                i += u64::from(end.min(self.end) - addr) + 1;
+                continue;
+            }
+            if self.is_free(addr) {
+                self.cursor = if addr == self.end { self.start } else { addr + 1 };
+                return Some(addr);
+            }
+            i += 1;
+        }
+        None
+    }
+}
+
+impl Allocator for Pool {
+    fn offer(&mut self, client: &ClientId, requested: Option<Ipv4Addr>) -> Option<Ipv4Addr> {
+        if let Some(&addr) = self.by_client.get(client) {
+            return Some(addr.into());
+        }
+        let addr = match requested.map(u32::from) {
+            Some(addr) if self.is_free(addr) => addr,
+            _ => self.next_free()?,
+        };
+        self.bind(client, addr);
+        Some(addr.into())
+    }
+
+    fn allocate(&mut self, client: &ClientId, addr: Ipv4Addr) -> bool {
+        let addr = u32::from(addr);
+        if !self.contains(addr.into()) {
+            return false;
+        }
+        if self.quarantined.contains(&addr) {
+            return false;
+        }
+        match self.bindings.get(&addr) {
+            Some(holder) if holder != client => false,
+            _ => {
+                self.bind(client, addr);
+                true
+            }
+        }
+    }
+
+    fn release(&mut self, client: &ClientId, addr: Ipv4Addr) {
+        let addr = u32::from(addr);
+        if self.bindings.get(&addr) == Some(client) {
+            self.bindings.remove(&addr);
+            self.by_client.remove(client);
+        }
+    }
+
+    fn quarantine(&mut self, addr: Ipv4Addr) {
+        let addr = u32::from(addr);
+        if let Some(holder) = self.bindings.remove(&addr) {
+            self.by_client.remove(&holder);
+        }
+        self.quarantined.insert(addr);
+    }
+
+    fn unquarantine(&mut self, addr: Ipv4Addr) {
+        self.quarantined.remove(&u32::from(addr));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(n: u8) -> ClientId {
+        ClientId(vec![2, 0, 0, 0, 0, n])
+    }
+
+    fn pool(start: [u8; 4], end: [u8; 4]) -> Pool {
+        Pool::new(start.into(), end.into()).unwrap()
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        assert!(Pool::new([10, 0, 0, 9].into(), [10, 0, 0, 1].into()).is_err());
+        assert!(Pool::from_cidr([10, 0, 0, 0].into(), 31).is_err());
+    }
+
+    #[test]
+    fn cidr_leaves_out_network_and_broadcast() {
+        let pool = Pool::from_cidr([10, 0, 0, 77].into(), 30).unwrap();
+        assert!(!pool.contains([10, 0, 0, 76].into()));
+        assert!(pool.contains([10, 0, 0, 77].into()));
+        assert!(pool.contains([10, 0, 0, 78].into()));
+        assert!(!pool.contains([10, 0, 0, 79].into()));
+    }
+
+    #[test]
+    fn never_offers_one_address_twice() {
+        let mut pool = pool([10, 0, 0, 1], [10, 0, 0, 3]);
+        pool.exclude([10, 0, 0, 2].into());
+        let a = pool.offer(&client(1), None).unwrap();
+        let b = pool.offer(&client(2), None).unwrap();
+        assert_ne!(a, b);
+        assert_ne!(a, Ipv4Addr::new(10, 0, 0, 2));
+        assert_ne!(b, Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(pool.offer(&client(3), None), None);
+        // A client keeps being offered what it holds.
+        assert_eq!(pool.offer(&client(1), Some(b)), Some(a));
+    }
+
+    #[test]
+    fn excluded_range_is_never_offered() {
+        let mut pool = Pool::from_cidr([10, 0, 0, 0].into(), 8).unwrap();
+        pool.exclude_range([10, 0, 0, 2].into(), [10, 255, 255, 253].into());
+        assert!(pool.contains([10, 0, 0, 1].into()));
+        assert!(!pool.contains([10, 0, 0, 2].into()));
+        assert!(!pool.contains([10, 255, 255, 253].into()));
+        assert!(!pool.allocate(&client(1), [10, 1, 2, 3].into()));
+        assert_eq!(pool.offer(&client(1), Some([10, 1, 2, 3].into())), Some([10, 0, 0, 1].into()));
+        assert_eq!(pool.offer(&client(2), None), Some([10, 255, 255, 254].into()));
+        assert_eq!(pool.offer(&client(3), None), None);
+    }
+
+    #[test]
+    fn honors_free_requested_address() {
+        let mut pool = pool([10, 0, 0, 1], [10, 0, 0, 10]);
+        assert_eq!(pool.offer(&client(1), Some([10, 0, 0, 7].into())), Some([10, 0, 0, 7].into()));
+        let other = pool.offer(&client(2), Some([10, 0, 0, 7].into())).unwrap();
+        assert_ne!(other, Ipv4Addr::new(10, 0, 0, 7));
+        assert_ne!(pool.offer(&client(3), Some([10, 0, 0, 99].into())), Some([10, 0, 0, 99].into()));
+    }
+
+    #[test]
+    fn allocate_refuses_what_is_not_ours_to_give() {
+        let mut pool = pool([10, 0, 0, 1], [10, 0, 0, 10]);
+        pool.exclude([10, 0, 0, 5].into());
+        assert!(pool.allocate(&client(1), [10, 0, 0, 4].into()));
+        assert!(pool.allocate(&client(1), [10, 0, 0, 4].into()));
+        assert!(!pool.allocate(&client(2), [10, 0, 0, 4].into()));
+        assert!(!pool.allocate(&client(2), [10, 0, 0, 5].into()));
+        assert!(!pool.allocate(&client(2), [10, 0, 0, 11].into()));
+    }
+
+    #[test]
+    fn rebinding_moves_a_client() {
+        let mut pool = pool([10, 0, 0, 1], [10, 0, 0, 10]);
+        assert!(pool.allocate(&client(1), [10, 0, 0, 4].into()));
+        assert!(pool.allocate(&client(1), [10, 0, 0, 6].into()));
+        assert!(pool.allocate(&client(2), [10, 0, 0, 4].into()));
+    }
+
+    #[test]
+    fn release_frees_only_for_the_holder() {
+        let mut pool = pool([10, 0, 0, 1], [10, 0, 0, 1]);
+        let addr = pool.offer(&client(1), None).unwrap();
+        pool.release(&client(2), addr);
+        assert_eq!(pool.offer(&client(2), None), None);
+        pool.release(&client(1), addr);
+        assert_eq!(pool.offer(&client(2), None), Some(addr));
+    }
+
+    #[test]
+    fn quarantine_takes_address_out_of_circulation() {
+        let mut pool = pool([10, 0, 0, 1], [10, 0, 0, 1]);
+        let addr = pool.offer(&client(1), None).unwrap();
+        pool.quarantine(addr);
+        assert_eq!(pool.offer(&client(1), None), None);
+        assert!(!pool.allocate(&client(2), addr));
+        pool.unquarantine(addr);
+        assert_eq!(pool.offer(&client(2), None), Some(addr));
+    }
+
+    #[test]
+    fn client_id_prefers_client_identifier() {
+        let chaddr = [2, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(ClientId::from_request(6, &chaddr, None), ClientId(chaddr[..6].to_vec()));
+        assert_eq!(ClientId::from_request(6, &chaddr, Some(&[1, 9])), ClientId(vec![1, 9]));
+        assert_eq!(ClientId::from_chaddr(99, &chaddr).0.len(), 16);
+    }
+}
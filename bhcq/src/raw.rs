@@ -0,0 +1,169 @@
+use std::error::Error as StdError;
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::os::unix::io::RawFd;
+
+const ETH_P_IP: u16 = 0x0800;
+const ETH_HEADER_SIZE: usize = 14;
+const IPV4_HEADER_SIZE: usize = 20;
+const UDP_HEADER_SIZE: usize = 8;
+
+// `struct ifreq` as filled in by SIOCGIFHWADDR.
+#[repr(C)]
+struct IfReqHwAddr {
+    ifr_name: [libc::c_char; libc::IFNAMSIZ],
+    ifr_hwaddr: libc::sockaddr,
+    _pad: [u8; 8],
+}
+
+// Sends IPv4/UDP datagrams as hand-made Ethernet frames, so that a client
+// can be reached by its hardware address before it has an IP address and
+// without touching the ARP table.
+pub struct RawSender {
+    fd: RawFd,
+    ifindex: libc::c_int,
+    mac: [u8; 6],
+}
+
+impl RawSender {
+    pub fn open(ifname: &str) -> Result<Self, Box<dyn StdError>> {
+        let c_ifname = CString::new(ifname)?;
+        let ifindex = unsafe { libc::if_nametoindex(c_ifname.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // Senders are called from the server's tasks, which must not block.
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_NONBLOCK, libc::c_int::from(ETH_P_IP.to_be())) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let mut sender = Self {
+            fd,
+            ifindex: ifindex as libc::c_int,
+            mac: [0; 6],
+        };
+        sender.mac = sender.hardware_address(&c_ifname)?;
+        Ok(sender)
+    }
+
+    fn hardware_address(&self, ifname: &CString) -> io::Result<[u8; 6]> {
+        let mut req: IfReqHwAddr = unsafe { mem::zeroed() };
+        let name = ifname.as_bytes();
+        if name.len() >= req.ifr_name.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "interface name too long"));
+        }
+        for (dst, &src) in req.ifr_name.iter_mut().zip(name) {
+            *dst = src as libc::c_char;
+        }
+        if unsafe { libc::ioctl(self.fd, libc::SIOCGIFHWADDR, &mut req as *mut IfReqHwAddr) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let hwaddr = req.ifr_hwaddr;
+        if hwaddr.sa_family != libc::ARPHRD_ETHER {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not an Ethernet interface"));
+        }
+        let mut mac = [0u8; 6];
+        for (dst, &src) in mac.iter_mut().zip(&hwaddr.sa_data[..6]) {
+            *dst = src as u8;
+        }
+        Ok(mac)
+    }
+
+    pub fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    // Fails with `WouldBlock` rather than waiting when the interface queue
+    // is full.
+    pub fn send_to(&self, payload: &[u8], src: (Ipv4Addr, u16), dst: (Ipv4Addr, u16), dst_mac: [u8; 6]) -> io::Result<()> {
+        let frame = build_frame(self.mac, dst_mac, src, dst, payload);
+        let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as libc::c_ushort;
+        addr.sll_protocol = ETH_P_IP.to_be();
+        addr.sll_ifindex = self.ifindex;
+        addr.sll_halen = 6;
+        addr.sll_addr[..6].copy_from_slice(&dst_mac);
+        let res = unsafe {
+            libc::sendto(
+                self.fd,
+                frame.as_ptr() as *const libc::c_void,
+                frame.len(),
+                0,
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RawSender {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+pub fn build_frame(src_mac: [u8; 6], dst_mac: [u8; 6], src: (Ipv4Addr, u16), dst: (Ipv4Addr, u16), payload: &[u8]) -> Vec<u8> {
+    let udp_len = UDP_HEADER_SIZE + payload.len();
+    let ip_len = IPV4_HEADER_SIZE + udp_len;
+    let mut frame = Vec::with_capacity(ETH_HEADER_SIZE + ip_len);
+
+    frame.extend_from_slice(&dst_mac);
+    frame.extend_from_slice(&src_mac);
+    frame.extend_from_slice(&ETH_P_IP.to_be_bytes());
+
+    let ip_start = frame.len();
+    frame.extend_from_slice(&[0x45, 0]);
+    frame.extend_from_slice(&(ip_len as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 0]); // identification, flags, fragment offset
+    frame.extend_from_slice(&[64, libc::IPPROTO_UDP as u8, 0, 0]);
+    frame.extend_from_slice(&src.0.octets());
+    frame.extend_from_slice(&dst.0.octets());
+    let ip_sum = checksum(&[&frame[ip_start..]]);
+    frame[ip_start + 10..ip_start + 12].copy_from_slice(&ip_sum.to_be_bytes());
+
+    let udp_start = frame.len();
+    frame.extend_from_slice(&src.1.to_be_bytes());
+    frame.extend_from_slice(&dst.1.to_be_bytes());
+    frame.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(payload);
+    let mut pseudo = [0u8; 12];
+    pseudo[0..4].copy_from_slice(&src.0.octets());
+    pseudo[4..8].copy_from_slice(&dst.0.octets());
+    pseudo[9] = libc::IPPROTO_UDP as u8;
+    pseudo[10..12].copy_from_slice(&(udp_len as u16).to_be_bytes());
+    let udp_sum = match checksum(&[&pseudo, &frame[udp_start..]]) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    frame[udp_start + 6..udp_start + 8].copy_from_slice(&udp_sum.to_be_bytes());
+
+    frame
+}
+
+// The Internet checksum (RFC 1071) over the concatenation of `parts`.
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    let mut odd = None;
+    for &byte in parts.iter().flat_map(|p| p.iter()) {
+        match odd.take() {
+            Some(hi) => sum += u32::from(u16::from_be_bytes([hi, byte])),
+            None => odd = Some(byte),
+        }
+    }
+    if let Some(hi) = odd {
+        sum += u32::from(u16::from_be_bytes([hi, 0]));
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
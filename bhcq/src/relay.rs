@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::future;
+use std::net;
+use std::task::Poll;
+use tokio::net::UdpSocket;
+use dhcpv4::{Message, OpCode};
+use dhcpv4::message::Header;
+
+use super::config::{self, Config};
+use super::raw::RawSender;
+use super::server::{self, Destination, Transmit};
+
+const BROADCAST_FLAG: u16 = 0x8000;
+
+// What to do with a message that came through the relay agent.
+pub enum Forward {
+    // Send to every configured server.
+    Servers(Vec<u8>),
+    // Send to a client on a downstream interface.
+    Client {
+        interface: String,
+        transmit: Transmit,
+    },
+}
+
+pub struct Builder {
+    config: Config,
+    socks: HashMap<String, UdpSocket>,
+    raws: HashMap<String, RawSender>,
+    upstream: Option<UdpSocket>,
+}
+
+impl Builder {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            socks: HashMap::new(),
+            raws: HashMap::new(),
+            upstream: None,
+        }
+    }
+
+    // Receives client requests on downstream `interface` and sends replies
+    // to clients there.
+    pub fn socket(mut self, interface: &str, sock: UdpSocket) -> Self {
+        self.socks.insert(interface.to_owned(), sock);
+        self
+    }
+
+    pub fn raw_sender(mut self, interface: &str, raw: RawSender) -> Self {
+        self.raws.insert(interface.to_owned(), raw);
+        self
+    }
+
+    // Talks to the servers: requests go out and replies come back through
+    // it.
+    pub fn upstream_socket(mut self, sock: UdpSocket) -> Self {
+        self.upstream = Some(sock);
+        self
+    }
+
+    pub fn build(self) -> Result<Relay, Box<dyn StdError>> {
+        let Builder { config, mut socks, mut raws, upstream } = self;
+        config.validate()?;
+        let relay = config.relay.clone().ok_or("no [relay] configured")?;
+        for name in socks.keys().chain(raws.keys()) {
+            if relay.interface(name).is_none() {
+                return Err(format!("relay interface {} is not configured", name).into());
+            }
+        }
+        let interfaces = relay.interfaces.iter()
+            .map(|iface| Interface {
+                sock: socks.remove(&iface.name),
+                raw: raws.remove(&iface.name),
+            })
+            .collect();
+        Ok(Relay {
+            config,
+            relay,
+            interfaces,
+            upstream,
+        })
+    }
+}
+
+// The sockets of each downstream interface, in configuration order.
+struct Interface {
+    sock: Option<UdpSocket>,
+    raw: Option<RawSender>,
+}
+
+// A relay agent (RFC 1542), forwarding requests from clients on downstream
+// interfaces to the configured servers and their replies back.
+pub struct Relay {
+    config: Config,
+    // The `[relay]` section of `config`, which `build` insists on.
+    relay: config::Relay,
+    interfaces: Vec<Interface>,
+    upstream: Option<UdpSocket>,
+}
+
+impl Relay {
+    pub fn builder(config: Config) -> Builder {
+        Builder::new(config)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Relays messages from the sockets given to the builder until receiving
+    // on one of them fails. Messages that cannot be relayed or forwarded are
+    // logged and skipped.
+    pub async fn run(&mut self) -> Result<(), Box<dyn StdError>> {
+        let mut upstream = self.upstream.take().ok_or("relay has no upstream socket")?;
+        // Downstream sockets by interface index, then the upstream one.
+        let mut socks = Vec::new();
+        for (index, iface) in self.interfaces.iter_mut().enumerate() {
+            if let Some(sock) = iface.sock.take() {
+                socks.push((index, sock));
+            }
+        }
+        let mut buf = vec![0u8; 4096];
+        let mut next = 0;
+        loop {
+            // Poll every socket, starting after the one served last, as the
+            // server does.
+            let (i, read, peer) = future::poll_fn(|cx| {
+                for offset in 0..=socks.len() {
+                    let i = (next + offset) % (socks.len() + 1);
+                    let sock = match socks.get_mut(i) {
+                        Some((_, sock)) => sock,
+                        None => &mut upstream,
+                    };
+                    if let Poll::Ready(res) = sock.poll_recv_from(cx, &mut buf) {
+                        return Poll::Ready(res.map(|(read, peer)| (i, read, peer)));
+                    }
+                }
+                Poll::Pending
+            }).await?;
+            next = (i + 1) % (socks.len() + 1);
+            let index = socks.get(i).map(|&(index, _)| index);
+            let name = index.map_or("upstream", |index| &self.relay.interfaces[index].name).to_owned();
+            let forward = match self.handle(index.map(|_| &name[..]), &buf[..read], peer) {
+                Ok(Some(forward)) => forward,
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!("{}: {}: {}", name, peer, e);
+                    continue;
+                },
+            };
+            let port = self.config.port;
+            match forward {
+                Forward::Servers(packet) => {
+                    for &server in &self.relay.servers {
+                        if let Err(e) = upstream.send_to(&packet, &net::SocketAddr::from((server, port))).await {
+                            eprintln!("{}: cannot forward to {}: {}", name, server, e);
+                        }
+                    }
+                },
+                Forward::Client { interface, transmit } => {
+                    let index = match self.relay.interfaces.iter().position(|iface| iface.name == interface) {
+                        Some(index) => index,
+                        None => {
+                            eprintln!("{}: not a relay interface", interface);
+                            continue;
+                        },
+                    };
+                    let source = (self.relay.interfaces[index].address, port);
+                    let raw = self.interfaces[index].raw.as_ref();
+                    let sock = match socks.iter_mut().find(|(i, _)| *i == index) {
+                        Some((_, sock)) => sock,
+                        None => {
+                            eprintln!("{}: no socket to reach the client through", interface);
+                            continue;
+                        },
+                    };
+                    if let Err(e) = server::send_to_client(sock, raw, &interface, source, &transmit).await {
+                        eprintln!("{}: cannot forward to client: {}", interface, e);
+                    }
+                },
+            }
+        }
+    }
+
+    // Processes one message: a request that came in on downstream
+    // `interface`, or a reply from a server, which may come in anywhere.
+    // Requests are only relayed from downstream interfaces.
+    pub fn handle(&self, interface: Option<&str>, bytes: &[u8], _peer: net::SocketAddr) -> Result<Option<Forward>, Box<dyn StdError>> {
+        let m = Message::new(bytes).ok_or("malformed size packet")?;
+        let hdr = m.header();
+        let relay = &self.relay;
+        match hdr.op_code() {
+            OpCode::BOOTREQUEST => {
+                let iface = match interface.and_then(|name| relay.interface(name)) {
+                    Some(iface) => iface,
+                    None => return Ok(None),
+                };
+                let hops = hdr.hops();
+                if hops >= relay.max_hops {
+                    return Err(format!("dropping request that has been through {} relay agents", hops).into());
+                }
+                // Our own address in giaddr means the request has been
+                // through here before.
+                let giaddr = hdr.giaddr();
+                if relay.interfaces.iter().any(|iface| iface.address == giaddr) {
+                    return Err(format!("dropping request looping back to us through {}", giaddr).into());
+                }
+                let mut packet = bytes.to_vec();
+                let mut hdr = Header::<&mut [u8]>::new(&mut packet[..Header::<()>::SIZE]).unwrap();
+                hdr.set_hops(hops + 1);
+                if giaddr.is_unspecified() {
+                    hdr.set_giaddr(iface.address);
+                }
+                Ok(Some(Forward::Servers(packet)))
+            },
+            OpCode::BOOTREPLY => {
+                // Replies for other relay agents are none of our business.
+                let giaddr = hdr.giaddr();
+                let iface = match relay.interfaces.iter().find(|iface| iface.address == giaddr) {
+                    Some(iface) => iface,
+                    None => return Ok(None),
+                };
+                Ok(Some(Forward::Client {
+                    interface: iface.name.clone(),
+                    transmit: Transmit {
+                        packet: bytes.to_vec(),
+                        dest: client_destination(&hdr),
+                    },
+                }))
+            },
+            _ => Err("unknown op code".into()),
+        }
+    }
+}
+
+// Where a reply relayed back to the client goes (RFC 1542, section 4.1.2).
+fn client_destination(repl_hdr: &Header<&[u8]>) -> Destination {
+    if repl_hdr.flags() & BROADCAST_FLAG != 0 {
+        return Destination::Broadcast;
+    }
+    // An ACK to an INFORM has ciaddr but no yiaddr.
+    let ciaddr = repl_hdr.ciaddr();
+    if !ciaddr.is_unspecified() {
+        return Destination::Unicast(ciaddr);
+    }
+    let yiaddr = repl_hdr.yiaddr();
+    if yiaddr.is_unspecified() {
+        return Destination::Broadcast;
+    }
+    Destination::Hardware(yiaddr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use dhcpv4::message;
+    use dhcpv4::options::end::*;
+
+    fn relay() -> Relay {
+        let config = toml::from_str(r#"
+            [relay]
+            servers = ["10.0.0.1"]
+            [[relay.interface]]
+            name = "eth1"
+            address = "10.1.0.1"
+            [[relay.interface]]
+            name = "eth2"
+            address = "10.2.0.1"
+        "#).unwrap();
+        Relay::builder(config).build().unwrap()
+    }
+
+    fn message(op_code: OpCode, hops: u8, giaddr: Ipv4Addr, yiaddr: Ipv4Addr) -> Vec<u8> {
+        message_with(op_code, hops, giaddr, yiaddr, Ipv4Addr::UNSPECIFIED)
+    }
+
+    fn message_with(op_code: OpCode, hops: u8, giaddr: Ipv4Addr, yiaddr: Ipv4Addr, ciaddr: Ipv4Addr) -> Vec<u8> {
+        let mut bldr = message::Builder::new();
+        let mut hdr = bldr.header_mut();
+        hdr.set_op_code(op_code);
+        hdr.set_hops(hops);
+        hdr.set_giaddr(giaddr);
+        hdr.set_yiaddr(yiaddr);
+        hdr.set_ciaddr(ciaddr);
+        {
+            let mut opts = bldr.options_builder();
+            opts.add_magic_cookie();
+            opts.add_end();
+        }
+        bldr.finish_owned().unwrap()
+    }
+
+    fn peer() -> net::SocketAddr {
+        ([10, 1, 0, 100], 68).into()
+    }
+
+    #[test]
+    fn build_needs_relay_section() {
+        let config: Config = toml::from_str(r#"
+            [[interface]]
+            name = "eth0"
+            server_identifier = "10.0.0.1"
+            [[subnet]]
+            network = "10.0.0.0/24"
+            interface = "eth0"
+        "#).unwrap();
+        assert!(Relay::builder(config).build().is_err());
+    }
+
+    #[test]
+    fn request_gets_our_giaddr() {
+        let relay = relay();
+        let packet = message(OpCode::BOOTREQUEST, 0, Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED);
+        let packet = match relay.handle(Some("eth2"), &packet, peer()).unwrap() {
+            Some(Forward::Servers(packet)) => packet,
+            _ => panic!("request not forwarded to the servers"),
+        };
+        let m = Message::new(&packet).unwrap();
+        assert_eq!(m.header().giaddr(), Ipv4Addr::new(10, 2, 0, 1));
+        assert_eq!(m.header().hops(), 1);
+    }
+
+    #[test]
+    fn request_keeps_other_agents_giaddr() {
+        let relay = relay();
+        let giaddr = Ipv4Addr::new(10, 9, 0, 1);
+        let packet = message(OpCode::BOOTREQUEST, 1, giaddr, Ipv4Addr::UNSPECIFIED);
+        let packet = match relay.handle(Some("eth1"), &packet, peer()).unwrap() {
+            Some(Forward::Servers(packet)) => packet,
+            _ => panic!("request not forwarded to the servers"),
+        };
+        let m = Message::new(&packet).unwrap();
+        assert_eq!(m.header().giaddr(), giaddr);
+        assert_eq!(m.header().hops(), 2);
+    }
+
+    #[test]
+    fn drops_looping_and_far_travelled_requests() {
+        let relay = relay();
+        let looped = message(OpCode::BOOTREQUEST, 1, Ipv4Addr::new(10, 1, 0, 1), Ipv4Addr::UNSPECIFIED);
+        assert!(relay.handle(Some("eth2"), &looped, peer()).is_err());
+        let far = message(OpCode::BOOTREQUEST, config::Relay::DEFAULT_MAX_HOPS, Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED);
+        assert!(relay.handle(Some("eth1"), &far, peer()).is_err());
+        // Not from a downstream interface.
+        let upstream = message(OpCode::BOOTREQUEST, 0, Ipv4Addr::UNSPECIFIED, Ipv4Addr::UNSPECIFIED);
+        assert!(relay.handle(None, &upstream, peer()).unwrap().is_none());
+    }
+
+    #[test]
+    fn reply_goes_to_interface_in_giaddr() {
+        let relay = relay();
+        let yiaddr = Ipv4Addr::new(10, 2, 0, 50);
+        let packet = message(OpCode::BOOTREPLY, 0, Ipv4Addr::new(10, 2, 0, 1), yiaddr);
+        match relay.handle(None, &packet, peer()).unwrap() {
+            Some(Forward::Client { interface, transmit }) => {
+                assert_eq!(interface, "eth2");
+                assert!(matches!(transmit.dest, Destination::Hardware(addr) if addr == yiaddr));
+            },
+            _ => panic!("reply not forwarded to a client"),
+        }
+        // Another relay agent's.
+        let other = message(OpCode::BOOTREPLY, 0, Ipv4Addr::new(10, 9, 0, 1), yiaddr);
+        assert!(relay.handle(None, &other, peer()).unwrap().is_none());
+    }
+
+    #[test]
+    fn inform_ack_goes_to_ciaddr() {
+        let relay = relay();
+        let ciaddr = Ipv4Addr::new(10, 2, 0, 60);
+        let packet = message_with(OpCode::BOOTREPLY, 0, Ipv4Addr::new(10, 2, 0, 1), Ipv4Addr::UNSPECIFIED, ciaddr);
+        match relay.handle(None, &packet, peer()).unwrap() {
+            Some(Forward::Client { transmit, .. }) => {
+                assert!(matches!(transmit.dest, Destination::Unicast(addr) if addr == ciaddr));
+            },
+            _ => panic!("reply not forwarded to a client"),
+        }
+    }
+}
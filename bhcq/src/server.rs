@@ -0,0 +1,1435 @@
+use nix::{errno::Errno, sys::socket::{self, AddressFamily, SockFlag, SockProtocol, SockType, sockopt, SockAddr}};
+use std::ffi::CString;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::net;
+use std::error::Error as StdError;
+use std::collections::HashMap;
+use std::future;
+use std::task::Poll;
+use tokio::net::UdpSocket;
+use dhcpv4::{Message, OpCode};
+use dhcpv4::message::Header;
+use dhcpv4::option::Code;
+use dhcpv4::options::OptionSet;
+use dhcpv4::parser::{Parser, Quirks};
+use dhcpv4::options::bytes::AddBytesExt;
+use dhcpv4::options::ip::GetIpExt;
+use dhcpv4::options::{
+    message_type::*,
+    subnet_mask::*,
+    routers::*,
+    lease_time::*,
+    domain_name_servers::*,
+    requested_ip_address::*,
+    server_identifier::*,
+    host_name::*,
+    client_identifier::*,
+    parameter_request_list::*,
+    maximum_dhcp_message_size::*,
+    domain_name::*,
+    relay_agent_information::*,
+};
+
+use super::config::{Config, Host, Ipv4Net, OptionName, Subnet};
+use super::handler::{Chain, Handler, Reply, Request, Verdict};
+use super::lease::{self, Lease, LeaseState, LeaseStore};
+use super::pool::{Allocator, ClientId, Pool};
+use super::raw::RawSender;
+
+const OFFER_TIME: u32 = 30;
+
+const BROADCAST_FLAG: u16 = 0x8000;
+
+// Options sent whether they were asked for or not, in this order.
+const MANDATORY_OPTIONS: [Code; 3] = [
+    Code::DHCP_MESSAGE_TYPE,
+    Code::SERVER_IDENTIFIER,
+    Code::IP_ADDRESS_LEASE_TIME,
+];
+
+// Where a reply goes (RFC 2131, section 4.1).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Destination {
+    // The relay agent the request came through.
+    Relay(net::Ipv4Addr),
+    // An address the client has already configured.
+    Unicast(net::Ipv4Addr),
+    // The address being handed to the client. The client does not answer
+    // ARP for it yet, so it has to be reached by its hardware address.
+    Hardware(net::Ipv4Addr),
+    Broadcast,
+}
+
+impl Destination {
+    pub fn select(requ_hdr: &Header<&[u8]>, repl_hdr: &Header<&[u8]>, message_type: MessageType) -> Self {
+        let giaddr = requ_hdr.giaddr();
+        if !giaddr.is_unspecified() {
+            return Destination::Relay(giaddr);
+        }
+        if message_type == MessageType::DHCPNAK {
+            return Destination::Broadcast;
+        }
+        let ciaddr = requ_hdr.ciaddr();
+        if !ciaddr.is_unspecified() {
+            return Destination::Unicast(ciaddr);
+        }
+        let yiaddr = repl_hdr.yiaddr();
+        if requ_hdr.flags() & BROADCAST_FLAG != 0 || yiaddr.is_unspecified() {
+            return Destination::Broadcast;
+        }
+        Destination::Hardware(yiaddr)
+    }
+
+    // `port` is the server port; clients listen on the one after it.
+    pub fn socket_addr(&self, port: u16) -> net::SocketAddr {
+        match *self {
+            Destination::Relay(addr) => (addr, port).into(),
+            Destination::Unicast(addr) | Destination::Hardware(addr) => (addr, port + 1).into(),
+            Destination::Broadcast => (net::Ipv4Addr::BROADCAST, port + 1).into(),
+        }
+    }
+}
+
+// A reply ready to go out, and where to.
+pub struct Transmit {
+    pub packet: Vec<u8>,
+    pub dest: Destination,
+}
+
+// The client states a DHCPREQUEST can come from (RFC 2131, section 4.3.2).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestState {
+    // Answering a DHCPOFFER from `server_identifier`.
+    Selecting { server_identifier: net::Ipv4Addr, requested: net::Ipv4Addr },
+    // Rebooted and wants its previous address back.
+    InitReboot { requested: net::Ipv4Addr },
+    // Extending a lease; RENEWING clients unicast to their server and
+    // REBINDING clients broadcast to any server. Both get the same answer,
+    // so they are not told apart.
+    RenewingOrRebinding { ciaddr: net::Ipv4Addr },
+}
+
+impl RequestState {
+    pub fn classify<M: GetIpExt>(requ_hdr: &Header<&[u8]>, opts_map: &M) -> Option<Self> {
+        let ciaddr = requ_hdr.ciaddr();
+        let requested = opts_map.get_requested_ip_address();
+        match (opts_map.get_server_identifier(), requested) {
+            (Some(server_identifier), Some(requested)) if ciaddr.is_unspecified() => {
+                Some(RequestState::Selecting { server_identifier, requested })
+            },
+            (None, Some(requested)) if ciaddr.is_unspecified() => {
+                Some(RequestState::InitReboot { requested })
+            },
+            (None, None) if !ciaddr.is_unspecified() => {
+                Some(RequestState::RenewingOrRebinding { ciaddr })
+            },
+            _ => None,
+        }
+    }
+}
+
+pub struct Builder {
+    config: Config,
+    socks: HashMap<String, UdpSocket>,
+    raws: HashMap<String, RawSender>,
+    allocators: HashMap<Ipv4Net, Box<dyn Allocator + Send>>,
+    leases: Option<LeaseStore>,
+    handlers: Chain,
+}
+
+impl Builder {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            socks: HashMap::new(),
+            raws: HashMap::new(),
+            allocators: HashMap::new(),
+            leases: None,
+            handlers: Chain::new(),
+        }
+    }
+
+    // Serves the interface named `interface` from `sock`.
+    pub fn socket(mut self, interface: &str, sock: UdpSocket) -> Self {
+        self.socks.insert(interface.to_owned(), sock);
+        self
+    }
+
+    // Sends replies to clients on `interface` without an address through
+    // `raw` rather than through the socket.
+    pub fn raw_sender(mut self, interface: &str, raw: RawSender) -> Self {
+        self.raws.insert(interface.to_owned(), raw);
+        self
+    }
+
+    // Replaces the pool described by the configuration for the subnet
+    // `network`.
+    pub fn allocator<A: Allocator + Send + 'static>(mut self, network: Ipv4Net, allocator: A) -> Self {
+        self.allocators.insert(network, Box::new(allocator));
+        self
+    }
+
+    // Replaces the lease store opened from `lease_file`.
+    pub fn lease_store(mut self, leases: LeaseStore) -> Self {
+        self.leases = Some(leases);
+        self
+    }
+
+    pub fn handler<H: Handler + 'static>(mut self, handler: H) -> Self {
+        self.handlers.push(handler);
+        self
+    }
+
+    pub fn build(self) -> Result<Server, Box<dyn StdError>> {
+        let Builder { config, mut socks, mut raws, mut allocators, leases, handlers } = self;
+        config.validate()?;
+        if config.relay.is_some() {
+            return Err("configuration is for a relay agent".into());
+        }
+        for name in socks.keys().chain(raws.keys()) {
+            if config.interface(name).is_none() {
+                return Err(format!("interface {} is not configured", name).into());
+            }
+        }
+        if let Some(network) = allocators.keys().find(|&&n| !config.subnets.iter().any(|s| s.network == n)) {
+            return Err(format!("subnet {} is not configured", network).into());
+        }
+        let leases = match leases {
+            Some(leases) => leases,
+            None => LeaseStore::open(&config.lease_file)
+                .map_err(|e| format!("{}: {}", config.lease_file.display(), e))?,
+        };
+        let interfaces = config.interfaces.iter()
+            .map(|iface| Interface {
+                sock: socks.remove(&iface.name),
+                raw: raws.remove(&iface.name),
+            })
+            .collect();
+        let mut scopes = Vec::new();
+        for subnet in &config.subnets {
+            let err = |e| format!("subnet {}: {}", subnet.network, e);
+            let allocator: Box<dyn Allocator + Send> = match allocators.remove(&subnet.network) {
+                Some(allocator) => allocator,
+                None => Box::new(config.build_pool(subnet).map_err(err)?),
+            };
+            let agent_pools = (0..subnet.agent_pools.len())
+                .map(|i| config.build_agent_pool(subnet, i).map_err(err))
+                .collect::<Result<_, _>>()?;
+            scopes.push(Scope {
+                network: subnet.network,
+                allocator,
+                agent_pools,
+            });
+        }
+        let now = lease::now();
+        for lease in leases.leases().filter(|l| !l.is_expired(now)) {
+            let scope = match scopes.iter_mut().find(|s| s.network.contains(lease.addr)) {
+                Some(scope) => scope,
+                None => continue,
+            };
+            match lease.state {
+                LeaseState::Declined => scope.allocator_of(lease.addr).quarantine(lease.addr),
+                _ => {
+                    scope.allocator_of(lease.addr).allocate(&lease.client, lease.addr);
+                },
+            }
+        }
+        let parser = Parser::new(config.parse_mode.mode());
+        Ok(Server {
+            config,
+            parser,
+            interfaces,
+            scopes,
+            leases,
+            handlers,
+            last_sweep: 0,
+        })
+    }
+}
+
+// What the server holds for each configured interface, in configuration
+// order.
+struct Interface {
+    sock: Option<UdpSocket>,
+    raw: Option<RawSender>,
+}
+
+// The allocators of each configured subnet, in configuration order.
+struct Scope {
+    network: Ipv4Net,
+    allocator: Box<dyn Allocator + Send>,
+    agent_pools: Vec<Pool>,
+}
+
+impl Scope {
+    // The allocator handing out `addr`.
+    fn allocator_of(&mut self, addr: net::Ipv4Addr) -> &mut dyn Allocator {
+        match self.agent_pools.iter_mut().find(|pool| pool.contains(addr)) {
+            Some(pool) => pool,
+            None => &mut *self.allocator,
+        }
+    }
+
+    // The allocator for clients behind agent pool `agent_pool`, if any.
+    fn allocator_for(&mut self, agent_pool: Option<usize>) -> &mut dyn Allocator {
+        match agent_pool {
+            Some(i) => &mut self.agent_pools[i],
+            None => &mut *self.allocator,
+        }
+    }
+}
+
+pub struct Server {
+    config: Config,
+    parser: Parser,
+    interfaces: Vec<Interface>,
+    scopes: Vec<Scope>,
+    leases: LeaseStore,
+    handlers: Chain,
+    last_sweep: u64,
+}
+
+impl Server {
+    pub fn builder(config: Config) -> Builder {
+        Builder::new(config)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // How many requests showed each quirk tolerated by lenient parsing.
+    pub fn quirks(&self) -> Quirks {
+        self.parser.seen()
+    }
+
+    pub fn leases(&self) -> &LeaseStore {
+        &self.leases
+    }
+
+    // Serves requests from the sockets given to the builder until one of
+    // them fails. Requests that cannot be handled are logged and skipped.
+    pub async fn run(&mut self) -> Result<(), Box<dyn StdError>> {
+        let mut socks = Vec::new();
+        for (index, iface) in self.interfaces.iter_mut().enumerate() {
+            if let Some(sock) = iface.sock.take() {
+                socks.push((index, sock));
+            }
+        }
+        if socks.is_empty() {
+            return Err("server has no socket".into());
+        }
+        let mut buf = vec![0u8; 4096];
+        let mut next = 0;
+        loop {
+            // Poll every socket, starting after the one served last so that
+            // a busy interface cannot starve the others.
+            let (i, read, peer) = future::poll_fn(|cx| {
+                for offset in 0..socks.len() {
+                    let i = (next + offset) % socks.len();
+                    if let Poll::Ready(res) = socks[i].1.poll_recv_from(cx, &mut buf) {
+                        return Poll::Ready(res.map(|(read, peer)| (i, read, peer)));
+                    }
+                }
+                Poll::Pending
+            }).await?;
+            next = (i + 1) % socks.len();
+            let (index, ref mut sock) = socks[i];
+            let iface = &self.config.interfaces[index];
+            let name = iface.name.clone();
+            let source = (iface.server_identifier, self.config.port);
+            match self.handle(&name, &buf[0..read], peer) {
+                Ok(Some(transmit)) => {
+                    let raw = self.interfaces[index].raw.as_ref();
+                    // The destination comes from the request, so a failed
+                    // send is that request's problem, not the server's.
+                    if let Err(e) = send_to_client(sock, raw, &name, source, &transmit).await {
+                        eprintln!("{}: cannot reply to {}: {}", name, peer, e);
+                    }
+                },
+                Ok(None) => {},
+                Err(e) => eprintln!("{}: {}: {}", name, peer, e),
+            }
+        }
+    }
+
+    // The subnet a request is served from: the one holding the relay
+    // agent's address if it was relayed, otherwise one attached to the
+    // interface it arrived on, preferring the one holding the client's
+    // current address.
+    fn select_subnet(&self, interface: &str, requ_hdr: &Header<&[u8]>) -> Option<usize> {
+        let subnets = &self.config.subnets;
+        let giaddr = requ_hdr.giaddr();
+        if !giaddr.is_unspecified() {
+            return subnets.iter().position(|s| s.network.contains(giaddr));
+        }
+        let attached = |s: &Subnet| s.interface.as_deref() == Some(interface);
+        let ciaddr = requ_hdr.ciaddr();
+        subnets.iter().position(|s| attached(s) && s.network.contains(ciaddr))
+            .or_else(|| subnets.iter().position(attached))
+    }
+
+    fn sweep(&mut self, now: u64) -> Result<(), Box<dyn StdError>> {
+        for lease in self.leases.expire(now)? {
+            let scope = match self.scopes.iter_mut().find(|s| s.network.contains(lease.addr)) {
+                Some(scope) => scope,
+                None => continue,
+            };
+            match lease.state {
+                LeaseState::Declined => scope.allocator_of(lease.addr).unquarantine(lease.addr),
+                _ => scope.allocator_of(lease.addr).release(&lease.client, lease.addr),
+            }
+        }
+        Ok(())
+    }
+
+    // Processes one request and returns the reply, if the request deserves
+    // one.
+    pub fn handle(&mut self, interface: &str, bytes: &[u8], peer: net::SocketAddr) -> Result<Option<Transmit>, Box<dyn StdError>> {
+        let server_identifier = self.config.interface(interface)
+            .ok_or_else(|| format!("interface {} is not configured", interface))?
+            .server_identifier;
+        let (m, quirks) = self.parser.parse(bytes)?;
+        if !quirks.is_empty() {
+            eprintln!("{}: {}: tolerated {}", interface, peer, quirks);
+        }
+        let requ_hdr = m.header();
+        if requ_hdr.op_code() != OpCode::BOOTREQUEST {
+            return Ok(None);
+        }
+        let mut opts_map = OptionSet::from_message(&m).ok_or("malformed options")?;
+        // Only a lenient parse lets these through; treat them as absent.
+        opts_map.remove_malformed();
+        let message_type = opts_map.get_message_type().ok_or("no message type")?;
+        let request = Request {
+            interface,
+            peer,
+            message: &m,
+            options: &opts_map,
+            message_type,
+            client: ClientId::from_request(requ_hdr.hlen(), requ_hdr.chaddr(), opts_map.get_client_identifier()),
+        };
+        if self.handlers.request(&request) == Verdict::Drop {
+            return Ok(None);
+        }
+        let index = self.select_subnet(interface, &requ_hdr).ok_or("no subnet for this request")?;
+        let client = &request.client;
+        let hostname = opts_map.get_host_name().map(<[u8]>::to_vec);
+        let now = lease::now();
+        if now != self.last_sweep {
+            self.sweep(now)?;
+            self.last_sweep = now;
+        }
+        let subnet = &self.config.subnets[index];
+        let chaddr = ClientId::from_chaddr(requ_hdr.hlen(), requ_hdr.chaddr());
+        // Only a relay agent may say where the client is; a client could
+        // claim any port.
+        let agent = opts_map.get_relay_agent_information()
+            .filter(|_| !requ_hdr.giaddr().is_unspecified());
+        // A reservation for a circuit or remote ID is shared by whatever
+        // sits behind it, so its address may still be leased to a device
+        // that was there before. Until that lease is gone, clients go to the
+        // pool, and REQUESTs for the address are NAKed.
+        let host = subnet.host(&chaddr.0, opts_map.get_client_identifier(), agent.as_ref())
+            .filter(|host| self.leases.get(host.address)
+                .is_none_or(|l| l.client == *client && l.state != LeaseState::Declined));
+        let agent_pool = subnet.agent_pool(agent.as_ref());
+        let lease_time = host.map_or_else(|| subnet.lease_time(), Host::lease_time);
+        let for_us = opts_map.get_server_identifier()
+            .is_none_or(|id| id == server_identifier);
+        let mut reply = match message_type {
+            MessageType::DHCPDISCOVER => {
+                let offered = match host {
+                    Some(host) => Some(host.address),
+                    None => self.scopes[index].allocator_for(agent_pool).offer(client, opts_map.get_requested_ip_address()),
+                };
+                let yiaddr = match offered {
+                    Some(yiaddr) => yiaddr,
+                    None => {
+                        eprintln!("pool of {} exhausted", subnet.network);
+                        return Ok(None);
+                    },
+                };
+                let bound = self.leases.get_by_client(client)
+                    .is_some_and(|l| l.addr == yiaddr && l.state == LeaseState::Bound);
+                if !bound {
+                    record(&mut self.leases, &mut self.scopes, Lease {
+                        client: client.clone(),
+                        addr: yiaddr,
+                        start: now,
+                        expiry: now + u64::from(OFFER_TIME),
+                        state: LeaseState::Offered,
+                        hostname,
+                    })?;
+                }
+                let mut reply = Reply::new(&requ_hdr, MessageType::DHCPOFFER);
+                reply.header_mut().set_yiaddr(yiaddr);
+                reply.add_lease_time(lease_time);
+                add_configured_options(&mut reply, subnet, host, server_identifier);
+                reply
+            },
+            MessageType::DHCPREQUEST => {
+                let state = RequestState::classify(&requ_hdr, &opts_map).ok_or("malformed REQUEST")?;
+                let addr = match state {
+                    RequestState::Selecting { server_identifier: selected, .. } if selected != server_identifier => {
+                        // The client took another server's offer, so ours can go.
+                        let offered = self.leases.get_by_client(client)
+                            .filter(|l| l.state == LeaseState::Offered)
+                            .map(|l| l.addr);
+                        if let Some(addr) = offered {
+                            self.leases.remove(addr)?;
+                            self.scopes[index].allocator_of(addr).release(client, addr);
+                        }
+                        return Ok(None);
+                    },
+                    RequestState::Selecting { requested, .. } => Some(requested),
+                    RequestState::InitReboot { requested } if !subnet.network.contains(requested) => None,
+                    RequestState::InitReboot { requested } if host.is_some() => Some(requested),
+                    RequestState::InitReboot { requested } => match self.leases.get_by_client(client) {
+                        Some(lease) if lease.addr == requested => Some(requested),
+                        Some(_) => None,
+                        // Some other server may know the client.
+                        None => return Ok(None),
+                    },
+                    RequestState::RenewingOrRebinding { ciaddr } => Some(ciaddr),
+                };
+                // A reserved client gets its reservation and nothing else.
+                let allocator = self.scopes[index].allocator_for(agent_pool);
+                let addr = addr.filter(|&addr| match host {
+                    Some(host) => host.address == addr,
+                    None => allocator.allocate(client, addr),
+                });
+                match addr {
+                    Some(addr) => {
+                        record(&mut self.leases, &mut self.scopes, Lease {
+                            client: client.clone(),
+                            addr,
+                            start: now,
+                            expiry: now + u64::from(lease_time),
+                            state: LeaseState::Bound,
+                            hostname,
+                        })?;
+                        let mut reply = Reply::new(&requ_hdr, MessageType::DHCPACK);
+                        {
+                            let mut repl_hdr = reply.header_mut();
+                            repl_hdr.set_ciaddr(requ_hdr.ciaddr());
+                            repl_hdr.set_yiaddr(addr);
+                        }
+                        reply.add_lease_time(lease_time);
+                        add_configured_options(&mut reply, subnet, host, server_identifier);
+                        reply
+                    },
+                    None => {
+                        let mut reply = Reply::new(&requ_hdr, MessageType::DHCPNAK);
+                        reply.add_server_identifier(server_identifier);
+                        reply
+                    },
+                }
+            },
+            MessageType::DHCPDECLINE => {
+                let addr = opts_map.get_requested_ip_address().ok_or("DECLINE without requested IP address")?;
+                let holds = self.leases.get_by_client(client).is_some_and(|l| l.addr == addr);
+                if for_us && holds {
+                    eprintln!("{} declined by {:02x?}, quarantined for {}s", addr, client.0, self.config.decline_time);
+                    self.scopes[index].allocator_of(addr).quarantine(addr);
+                    record(&mut self.leases, &mut self.scopes, Lease {
+                        client: client.clone(),
+                        addr,
+                        start: now,
+                        expiry: now + u64::from(self.config.decline_time),
+                        state: LeaseState::Declined,
+                        hostname: None,
+                    })?;
+                }
+                return Ok(None);
+            },
+            MessageType::DHCPRELEASE => {
+                let addr = requ_hdr.ciaddr();
+                let holds = self.leases.get_by_client(client).is_some_and(|l| l.addr == addr);
+                if for_us && holds {
+                    self.leases.remove(addr)?;
+                    self.scopes[index].allocator_of(addr).release(client, addr);
+                }
+                return Ok(None);
+            },
+            MessageType::DHCPINFORM => {
+                let mut reply = Reply::new(&requ_hdr, MessageType::DHCPACK);
+                reply.header_mut().set_ciaddr(requ_hdr.ciaddr());
+                add_configured_options(&mut reply, subnet, host, server_identifier);
+                reply
+            },
+            _ => return Ok(None),
+        };
+        if let Some(requested) = opts_map.get_parameter_request_list() {
+            let requested: Vec<Code> = requested.collect();
+            let options = host.map_or(&subnet.options, |h| &h.options);
+            select_requested_options(&mut reply, &requested, options.always_send.as_deref().unwrap_or(&[]));
+        }
+        // Relay agent information goes back as it came, after everything
+        // else (RFC 3046, section 2.2), but only to a relay agent.
+        let info = opts_map.get(Code::RELAY_AGENT_INFORMATION)
+            .filter(|_| !requ_hdr.giaddr().is_unspecified());
+        if let Some(info) = info {
+            reply.add_bytes(Code::RELAY_AGENT_INFORMATION, info);
+        }
+        if self.handlers.reply(&request, &mut reply) == Verdict::Drop {
+            return Ok(None);
+        }
+        let message_type = reply.message_type().ok_or("reply without message type")?;
+        if message_type == MessageType::DHCPNAK && !requ_hdr.giaddr().is_unspecified() {
+            let flags = requ_hdr.flags() | BROADCAST_FLAG;
+            reply.header_mut().set_flags(flags);
+        }
+        if let Some(size) = opts_map.get_maximum_dhcp_message_size() {
+            reply.set_max_size(usize::from(size).saturating_sub(Message::<()>::IP_UDP_HEADER_SIZE));
+        }
+        let dest = Destination::select(&requ_hdr, &reply.header(), message_type);
+        Ok(Some(Transmit {
+            packet: reply.finish()?,
+            dest,
+        }))
+    }
+}
+
+// Records `lease`, handing back to its pool whatever address the client held
+// before, which may belong to another subnet.
+fn record(leases: &mut LeaseStore, scopes: &mut [Scope], lease: Lease) -> Result<(), Box<dyn StdError>> {
+    let old = leases.get_by_client(&lease.client)
+        .filter(|old| old.addr != lease.addr && lease.state != LeaseState::Declined)
+        .map(|old| old.addr);
+    if let Some(old) = old {
+        if let Some(scope) = scopes.iter_mut().find(|s| s.network.contains(old)) {
+            scope.allocator_of(old).release(&lease.client, old);
+        }
+    }
+    leases.insert(lease)?;
+    Ok(())
+}
+
+pub fn bind(ifname: CString, port: u16) -> Result<UdpSocket, Box<dyn StdError>> {
+    bind_socket(Some(ifname), port)
+}
+
+// A socket on `port` of every interface, for a relay agent to talk to its
+// servers through.
+pub fn bind_any(port: u16) -> Result<UdpSocket, Box<dyn StdError>> {
+    bind_socket(None, port)
+}
+
+fn bind_socket(ifname: Option<CString>, port: u16) -> Result<UdpSocket, Box<dyn StdError>> {
+    let fd = socket::socket(
+        AddressFamily::Inet,
+        SockType::Datagram,
+        SockFlag::empty(),
+        Some(SockProtocol::Udp),
+    )?;
+    // Every interface gets its own socket on the server port, which the
+    // kernel only allows when each is tied to its device before binding.
+    socket::setsockopt(fd, sockopt::ReuseAddr, &true)?;
+    if let Some(ifname) = ifname {
+        unsafe {
+            let res = libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                ifname.as_ptr() as *const libc::c_void,
+                ifname.as_bytes().len() as u32,
+            );
+            Errno::result(res).map(drop)
+        }?;
+    }
+    let addr: net::SocketAddr = net::SocketAddrV4::new(net::Ipv4Addr::new(0, 0, 0, 0), port).into();
+    socket::bind(fd, &SockAddr::Inet(socket::InetAddr::from_std(&addr)))?;
+    let std_sock = unsafe { net::UdpSocket::from_raw_fd(fd) };
+    let sock = UdpSocket::from_std(std_sock)?;
+    sock.set_broadcast(true)?;
+    Ok(sock)
+}
+
+// Sends `transmit` to a client on interface `ifname` from `source`, the
+// interface address and server port. A client that has no address yet is
+// reached through `raw` if given, otherwise through an ARP entry, and failing
+// that, including when the raw socket would block, by broadcast.
+pub(crate) async fn send_to_client(
+    sock: &mut UdpSocket,
+    raw: Option<&RawSender>,
+    ifname: &str,
+    source: (net::Ipv4Addr, u16),
+    transmit: &Transmit,
+) -> Result<(), Box<dyn StdError>> {
+    let (_, port) = source;
+    let dest = match (transmit.dest, raw) {
+        (Destination::Hardware(addr), Some(raw)) => {
+            match send_raw(raw, &transmit.packet, source, addr) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    eprintln!("{}: cannot send raw frame to {}, broadcasting instead: {}", ifname, addr, e);
+                    Destination::Broadcast
+                },
+            }
+        },
+        (Destination::Hardware(addr), None) => {
+            match set_arp_entry(sock.as_raw_fd(), ifname, addr, &transmit.packet) {
+                Ok(()) => transmit.dest,
+                Err(e) => {
+                    eprintln!("{}: cannot add ARP entry for {}, broadcasting instead: {}", ifname, addr, e);
+                    Destination::Broadcast
+                },
+            }
+        },
+        (dest, _) => dest,
+    };
+    sock.send_to(&transmit.packet, &dest.socket_addr(port)).await?;
+    Ok(())
+}
+
+fn send_raw(raw: &RawSender, packet: &[u8], source: (net::Ipv4Addr, u16), addr: net::Ipv4Addr) -> Result<(), Box<dyn StdError>> {
+    let m = Message::new(packet).ok_or("malformed reply")?;
+    let repl_hdr = m.header();
+    if u16::from(repl_hdr.htype()) != libc::ARPHRD_ETHER || repl_hdr.hlen() != 6 {
+        return Err("not an Ethernet client".into());
+    }
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&repl_hdr.chaddr()[..6]);
+    let (_, port) = source;
+    raw.send_to(packet, source, (addr, port + 1), mac)?;
+    Ok(())
+}
+
+// Teaches the kernel the hardware address behind `addr` so that a reply can
+// be unicast to a client that cannot answer ARP yet.
+fn set_arp_entry(fd: RawFd, ifname: &str, addr: net::Ipv4Addr, packet: &[u8]) -> Result<(), Box<dyn StdError>> {
+    const ATF_COM: libc::c_int = 0x02;
+    let m = Message::new(packet).ok_or("malformed reply")?;
+    let repl_hdr = m.header();
+    if u16::from(repl_hdr.htype()) != libc::ARPHRD_ETHER || repl_hdr.hlen() != 6 {
+        return Err("not an Ethernet client".into());
+    }
+    let mut req: libc::arpreq = unsafe { std::mem::zeroed() };
+    let pa = &mut req.arp_pa as *mut libc::sockaddr as *mut libc::sockaddr_in;
+    unsafe {
+        (*pa).sin_family = libc::AF_INET as libc::sa_family_t;
+        (*pa).sin_addr.s_addr = u32::from(addr).to_be();
+    }
+    req.arp_ha.sa_family = libc::ARPHRD_ETHER;
+    for (dst, &src) in req.arp_ha.sa_data.iter_mut().zip(&repl_hdr.chaddr()[..6]) {
+        *dst = src as libc::c_char;
+    }
+    req.arp_flags = ATF_COM;
+    if ifname.len() >= req.arp_dev.len() {
+        return Err("interface name too long".into());
+    }
+    for (dst, &src) in req.arp_dev.iter_mut().zip(ifname.as_bytes()) {
+        *dst = src as libc::c_char;
+    }
+    let res = unsafe { libc::ioctl(fd, libc::SIOCSARP, &req as *const libc::arpreq) };
+    Errno::result(res)?;
+    Ok(())
+}
+
+// Drops the options the client did not ask for, unless they are mandatory or
+// configured to be sent anyway, and puts the rest in the order the client
+// asked for them (RFC 2131, section 4.3.1). Clients that send no parameter
+// request list get everything configured.
+fn select_requested_options(reply: &mut Reply, requested: &[Code], always_send: &[OptionName]) {
+    reply.retain_options(|code| {
+        MANDATORY_OPTIONS.contains(&code)
+            || requested.contains(&code)
+            || always_send.iter().any(|name| name.code() == code)
+    });
+    reply.sort_options_by_key(|code| {
+        match MANDATORY_OPTIONS.iter().position(|&c| c == code) {
+            Some(i) => (0, i),
+            None => match requested.iter().position(|&c| c == code) {
+                Some(i) => (1, i),
+                None => (2, 0),
+            },
+        }
+    });
+}
+
+fn add_configured_options(reply: &mut Reply, subnet: &Subnet, host: Option<&Host>, server_identifier: net::Ipv4Addr) {
+    let options = host.map_or(&subnet.options, |h| &h.options);
+    reply.add_subnet_mask(subnet.network.mask());
+    reply.add_server_identifier(server_identifier);
+    if let Some(ref routers) = options.routers {
+        reply.add_routers(routers);
+    }
+    if let Some(ref servers) = options.domain_name_servers {
+        reply.add_domain_name_servers(servers);
+    }
+    if let Some(ref domain_name) = options.domain_name {
+        reply.add_domain_name(domain_name.as_bytes());
+    }
+    if let Some(host) = host {
+        if let Some(ref host_name) = host.host_name {
+            reply.add_host_name(host_name.as_bytes());
+        }
+        let mut repl_hdr = reply.header_mut();
+        if let Some(next_server) = host.next_server {
+            repl_hdr.set_siaddr(next_server);
+        }
+        if let Some(ref boot_file) = host.boot_file {
+            repl_hdr.file()[..boot_file.len()].copy_from_slice(boot_file.as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::net::Ipv4Addr;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use dhcpv4::message;
+    use dhcpv4::options::end::*;
+
+    const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    // One link with a pool, and a subnet behind a relay agent at
+    // 10.1.0.1.
+    const CONFIG: &str = r#"
+        decline_time = 600
+        [[interface]]
+        name = "eth0"
+        server_identifier = "10.0.0.1"
+        [[subnet]]
+        network = "10.0.0.0/24"
+        interface = "eth0"
+        pool = { start = "10.0.0.100", end = "10.0.0.199" }
+        [subnet.options]
+        routers = ["10.0.0.1"]
+        domain_name_servers = ["10.0.0.53"]
+        domain_name = "example.org"
+        [[subnet]]
+        network = "10.1.0.0/24"
+        pool = { start = "10.1.0.100", end = "10.1.0.199" }
+    "#;
+
+    // A server loaded from `config`, with a directory of its own for the
+    // configuration and lease journal, removed when dropped.
+    struct Fixture {
+        dir: PathBuf,
+        server: Server,
+    }
+
+    impl Fixture {
+        fn new(config: &str) -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let name = format!("bhcq-server-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::SeqCst));
+            let dir = std::env::temp_dir().join(name);
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir(&dir).unwrap();
+            let path = dir.join("bhcq.toml");
+            fs::write(&path, format!("lease_file = {:?}\n{}", dir.join("leases"), config)).unwrap();
+            let server = Server::builder(Config::load(&path).unwrap()).build().unwrap();
+            Fixture { dir, server }
+        }
+
+        fn send(&mut self, req: &Req) -> Option<Answer> {
+            let peer = match req.giaddr {
+                giaddr if giaddr.is_unspecified() => (Ipv4Addr::UNSPECIFIED, 68).into(),
+                giaddr => (giaddr, 67).into(),
+            };
+            self.server.handle("eth0", &req.encode(), peer).unwrap()
+                .map(|transmit| Answer { packet: transmit.packet, dest: transmit.dest })
+        }
+
+        // Takes client `n` through DISCOVER, OFFER, REQUEST and ACK.
+        fn bind(&mut self, n: u8) -> Ipv4Addr {
+            let offer = self.send(&Req::new(MessageType::DHCPDISCOVER, n)).unwrap();
+            assert_eq!(offer.message_type(), MessageType::DHCPOFFER);
+            let addr = offer.yiaddr();
+            let ack = self.send(&Req::selecting(n, SERVER, addr)).unwrap();
+            assert_eq!(ack.message_type(), MessageType::DHCPACK);
+            assert_eq!(ack.yiaddr(), addr);
+            addr
+        }
+
+        fn lease(&self, addr: Ipv4Addr) -> Option<&Lease> {
+            self.server.leases().get(addr)
+        }
+
+        // Whether the pool would still give `addr` to a client that holds
+        // nothing. Binds it if so.
+        fn pool_gives(&mut self, addr: Ipv4Addr) -> bool {
+            let scope = self.server.scopes.iter_mut().find(|s| s.network.contains(addr)).unwrap();
+            scope.allocator_of(addr).allocate(&ClientId(vec![0xff; 6]), addr)
+        }
+
+        fn reopen_leases(&self) -> LeaseStore {
+            LeaseStore::open(self.dir.join("leases")).unwrap()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn chaddr(n: u8) -> [u8; 6] {
+        [2, 0, 0, 0, 0, n]
+    }
+
+    fn client(n: u8) -> ClientId {
+        ClientId(chaddr(n).to_vec())
+    }
+
+    // A request from client `n`, whose hardware address is
+    // 02:00:00:00:00:<n>.
+    struct Req {
+        n: u8,
+        ciaddr: Ipv4Addr,
+        giaddr: Ipv4Addr,
+        flags: u16,
+        options: OptionSet,
+    }
+
+    impl Req {
+        fn new(message_type: MessageType, n: u8) -> Self {
+            let mut options = OptionSet::new();
+            options.add_message_type(message_type);
+            Req {
+                n,
+                ciaddr: Ipv4Addr::UNSPECIFIED,
+                giaddr: Ipv4Addr::UNSPECIFIED,
+                flags: 0,
+                options,
+            }
+        }
+
+        // A REQUEST answering an OFFER of `addr` from `server`.
+        fn selecting(n: u8, server: Ipv4Addr, addr: Ipv4Addr) -> Self {
+            Req::new(MessageType::DHCPREQUEST, n)
+                .option(Code::SERVER_IDENTIFIER, &server.octets())
+                .option(Code::REQUESTED_IP_ADDRESS, &addr.octets())
+        }
+
+        fn ciaddr(mut self, ciaddr: Ipv4Addr) -> Self {
+            self.ciaddr = ciaddr;
+            self
+        }
+
+        fn giaddr(mut self, giaddr: Ipv4Addr) -> Self {
+            self.giaddr = giaddr;
+            self
+        }
+
+        fn flags(mut self, flags: u16) -> Self {
+            self.flags = flags;
+            self
+        }
+
+        fn option(mut self, code: Code, value: &[u8]) -> Self {
+            self.options.add_bytes(code, value);
+            self
+        }
+
+        fn encode(&self) -> Vec<u8> {
+            let mut bldr = message::Builder::new();
+            {
+                let mut hdr = bldr.header_mut();
+                hdr.set_op_code(OpCode::BOOTREQUEST);
+                hdr.set_htype(1);
+                hdr.set_hlen(6);
+                hdr.set_xid(0x1234_5678);
+                hdr.set_flags(self.flags);
+                hdr.set_ciaddr(self.ciaddr);
+                hdr.set_giaddr(self.giaddr);
+                hdr.chaddr()[..6].copy_from_slice(&chaddr(self.n));
+            }
+            {
+                let mut opts = bldr.options_builder();
+                opts.add_magic_cookie();
+                self.options.write(&mut opts);
+                opts.add_end();
+            }
+            bldr.finish_owned().unwrap()
+        }
+    }
+
+    struct Answer {
+        packet: Vec<u8>,
+        dest: Destination,
+    }
+
+    impl Answer {
+        fn message(&self) -> Message<&[u8]> {
+            Message::new(&self.packet).unwrap()
+        }
+
+        fn options(&self) -> OptionSet {
+            OptionSet::from_message(&self.message()).unwrap()
+        }
+
+        fn message_type(&self) -> MessageType {
+            self.options().get_message_type().unwrap()
+        }
+
+        fn yiaddr(&self) -> Ipv4Addr {
+            self.message().header().yiaddr()
+        }
+
+        fn codes(&self) -> Vec<u8> {
+            self.options().iter().map(|(Code(code), _)| code).collect()
+        }
+    }
+
+    #[test]
+    fn decline_quarantines_and_is_journaled() {
+        let mut fx = Fixture::new(CONFIG);
+        let addr = fx.bind(1);
+        let decline = Req::new(MessageType::DHCPDECLINE, 1)
+            .option(Code::SERVER_IDENTIFIER, &SERVER.octets())
+            .option(Code::REQUESTED_IP_ADDRESS, &addr.octets());
+        assert!(fx.send(&decline).is_none());
+        let lease = fx.lease(addr).unwrap().clone();
+        assert_eq!(lease.state, LeaseState::Declined);
+        assert_eq!(lease.expiry - lease.start, 600);
+        assert!(fx.server.leases().get_by_client(&client(1)).is_none());
+        assert!(!fx.pool_gives(addr));
+        // Nobody is offered it, not even on request.
+        let offer = fx.send(&Req::new(MessageType::DHCPDISCOVER, 2).option(Code::REQUESTED_IP_ADDRESS, &addr.octets())).unwrap();
+        assert_ne!(offer.yiaddr(), addr);
+        assert_eq!(fx.reopen_leases().get(addr).map(|l| l.state), Some(LeaseState::Declined));
+    }
+
+    #[test]
+    fn decline_of_what_the_client_does_not_hold_is_ignored() {
+        let mut fx = Fixture::new(CONFIG);
+        let addr = fx.bind(1);
+        let decline = Req::new(MessageType::DHCPDECLINE, 2)
+            .option(Code::SERVER_IDENTIFIER, &SERVER.octets())
+            .option(Code::REQUESTED_IP_ADDRESS, &addr.octets());
+        assert!(fx.send(&decline).is_none());
+        assert_eq!(fx.lease(addr).map(|l| l.state), Some(LeaseState::Bound));
+    }
+
+    #[test]
+    fn release_frees_the_holders_address_only() {
+        let mut fx = Fixture::new(CONFIG);
+        let addr = fx.bind(1);
+        let release = |n| Req::new(MessageType::DHCPRELEASE, n)
+            .ciaddr(addr)
+            .option(Code::SERVER_IDENTIFIER, &SERVER.octets());
+        assert!(fx.send(&release(2)).is_none());
+        assert_eq!(fx.lease(addr).map(|l| &l.client), Some(&client(1)));
+        assert!(!fx.pool_gives(addr));
+        assert!(fx.send(&release(1)).is_none());
+        assert!(fx.lease(addr).is_none());
+        assert!(fx.reopen_leases().get(addr).is_none());
+        assert!(fx.pool_gives(addr));
+    }
+
+    #[test]
+    fn inform_answers_ciaddr_without_a_lease() {
+        let mut fx = Fixture::new(CONFIG);
+        let ciaddr = Ipv4Addr::new(10, 0, 0, 7);
+        let ack = fx.send(&Req::new(MessageType::DHCPINFORM, 1).ciaddr(ciaddr)).unwrap();
+        assert_eq!(ack.message_type(), MessageType::DHCPACK);
+        assert_eq!(ack.dest, Destination::Unicast(ciaddr));
+        assert_eq!(ack.message().header().ciaddr(), ciaddr);
+        assert_eq!(ack.yiaddr(), Ipv4Addr::UNSPECIFIED);
+        let opts = ack.options();
+        assert_eq!(opts.get(Code::IP_ADDRESS_LEASE_TIME), None);
+        assert_eq!(opts.get_server_identifier(), Some(SERVER));
+        assert_eq!(opts.get(Code::DOMAIN_NAME), Some(&b"example.org"[..]));
+        assert!(fx.lease(ciaddr).is_none());
+        assert!(fx.server.leases().get_by_client(&client(1)).is_none());
+    }
+
+    #[test]
+    fn classifies_request_states() {
+        let addr = Ipv4Addr::new(10, 0, 0, 100);
+        let other = Ipv4Addr::new(10, 0, 0, 2);
+        let cases = [
+            (Ipv4Addr::UNSPECIFIED, Some(SERVER), Some(addr), Some(RequestState::Selecting { server_identifier: SERVER, requested: addr })),
+            (Ipv4Addr::UNSPECIFIED, None, Some(addr), Some(RequestState::InitReboot { requested: addr })),
+            (addr, None, None, Some(RequestState::RenewingOrRebinding { ciaddr: addr })),
+            // Neither ciaddr nor a requested address.
+            (Ipv4Addr::UNSPECIFIED, None, None, None),
+            (Ipv4Addr::UNSPECIFIED, Some(SERVER), None, None),
+            // ciaddr must be zero in SELECTING and INIT-REBOOT.
+            (addr, Some(SERVER), Some(addr), None),
+            (addr, None, Some(other), None),
+        ];
+        for &(ciaddr, server, requested, expected) in &cases {
+            let mut req = Req::new(MessageType::DHCPREQUEST, 1).ciaddr(ciaddr);
+            if let Some(server) = server {
+                req = req.option(Code::SERVER_IDENTIFIER, &server.octets());
+            }
+            if let Some(requested) = requested {
+                req = req.option(Code::REQUESTED_IP_ADDRESS, &requested.octets());
+            }
+            let packet = req.encode();
+            let m = Message::new(&packet).unwrap();
+            assert_eq!(RequestState::classify(&m.header(), &req.options), expected, "{:?}", (ciaddr, server, requested));
+        }
+    }
+
+    #[test]
+    fn selecting_binds_the_offer() {
+        let mut fx = Fixture::new(CONFIG);
+        let offer = fx.send(&Req::new(MessageType::DHCPDISCOVER, 1)).unwrap();
+        let addr = offer.yiaddr();
+        assert_eq!(fx.lease(addr).map(|l| l.state), Some(LeaseState::Offered));
+        let ack = fx.send(&Req::selecting(1, SERVER, addr)).unwrap();
+        assert_eq!(ack.message_type(), MessageType::DHCPACK);
+        assert_eq!(ack.yiaddr(), addr);
+        let lease = fx.lease(addr).unwrap();
+        assert_eq!((&lease.client, lease.state), (&client(1), LeaseState::Bound));
+        assert_eq!(lease.expiry - lease.start, u64::from(Config::DEFAULT_LEASE_TIME));
+        assert!(!fx.pool_gives(addr));
+    }
+
+    #[test]
+    fn selecting_another_server_drops_our_offer() {
+        let mut fx = Fixture::new(CONFIG);
+        let addr = fx.send(&Req::new(MessageType::DHCPDISCOVER, 1)).unwrap().yiaddr();
+        assert!(fx.send(&Req::selecting(1, Ipv4Addr::new(10, 0, 0, 2), addr)).is_none());
+        assert!(fx.lease(addr).is_none());
+        assert!(fx.pool_gives(addr));
+    }
+
+    #[test]
+    fn selecting_someone_elses_address_is_naked() {
+        let mut fx = Fixture::new(CONFIG);
+        let addr = fx.bind(1);
+        let nak = fx.send(&Req::selecting(2, SERVER, addr)).unwrap();
+        assert_eq!(nak.message_type(), MessageType::DHCPNAK);
+        assert_eq!(nak.dest, Destination::Broadcast);
+        assert_eq!(nak.options().get_server_identifier(), Some(SERVER));
+        assert_eq!(fx.lease(addr).map(|l| &l.client), Some(&client(1)));
+        assert!(fx.server.leases().get_by_client(&client(2)).is_none());
+    }
+
+    #[test]
+    fn init_reboot_confirms_or_naks() {
+        let mut fx = Fixture::new(CONFIG);
+        let addr = fx.bind(1);
+        let reboot = |n, addr: Ipv4Addr| Req::new(MessageType::DHCPREQUEST, n)
+            .option(Code::REQUESTED_IP_ADDRESS, &addr.octets());
+        let ack = fx.send(&reboot(1, addr)).unwrap();
+        assert_eq!(ack.message_type(), MessageType::DHCPACK);
+        assert_eq!(ack.yiaddr(), addr);
+        // Not the address it holds.
+        let nak = fx.send(&reboot(1, Ipv4Addr::new(10, 0, 0, 150))).unwrap();
+        assert_eq!(nak.message_type(), MessageType::DHCPNAK);
+        assert_eq!(fx.lease(addr).map(|l| l.state), Some(LeaseState::Bound));
+        // Moved to another link.
+        let nak = fx.send(&reboot(1, Ipv4Addr::new(192, 168, 0, 10))).unwrap();
+        assert_eq!(nak.message_type(), MessageType::DHCPNAK);
+        assert_eq!(nak.dest, Destination::Broadcast);
+        // Some other server may know a client we do not.
+        assert!(fx.send(&reboot(2, Ipv4Addr::new(10, 0, 0, 150))).is_none());
+        assert!(fx.lease(Ipv4Addr::new(10, 0, 0, 150)).is_none());
+    }
+
+    #[test]
+    fn renewing_extends_the_lease() {
+        let mut fx = Fixture::new(CONFIG);
+        let addr = fx.bind(1);
+        let ack = fx.send(&Req::new(MessageType::DHCPREQUEST, 1).ciaddr(addr)).unwrap();
+        assert_eq!(ack.message_type(), MessageType::DHCPACK);
+        assert_eq!(ack.yiaddr(), addr);
+        assert_eq!(ack.message().header().ciaddr(), addr);
+        assert_eq!(ack.dest, Destination::Unicast(addr));
+        assert_eq!(fx.lease(addr).map(|l| l.state), Some(LeaseState::Bound));
+    }
+
+    #[test]
+    fn renewing_someone_elses_address_is_naked() {
+        let mut fx = Fixture::new(CONFIG);
+        let addr = fx.bind(1);
+        let nak = fx.send(&Req::new(MessageType::DHCPREQUEST, 2).ciaddr(addr)).unwrap();
+        assert_eq!(nak.message_type(), MessageType::DHCPNAK);
+        assert_eq!(nak.dest, Destination::Broadcast);
+        assert_eq!(fx.lease(addr).map(|l| &l.client), Some(&client(1)));
+    }
+
+    #[test]
+    fn selects_reply_destination() {
+        let zero = Ipv4Addr::UNSPECIFIED;
+        let relay = Ipv4Addr::new(10, 1, 0, 1);
+        let ciaddr = Ipv4Addr::new(10, 0, 0, 7);
+        let yiaddr = Ipv4Addr::new(10, 0, 0, 100);
+        let (ack, nak, offer) = (MessageType::DHCPACK, MessageType::DHCPNAK, MessageType::DHCPOFFER);
+        // giaddr, ciaddr, flags, yiaddr, message type.
+        let cases = [
+            // A relay agent gets everything, even NAKs.
+            (relay, zero, 0, yiaddr, offer, Destination::Relay(relay)),
+            (relay, ciaddr, BROADCAST_FLAG, yiaddr, ack, Destination::Relay(relay)),
+            (relay, zero, 0, zero, nak, Destination::Relay(relay)),
+            // NAKs are broadcast whatever else the request says.
+            (zero, ciaddr, 0, zero, nak, Destination::Broadcast),
+            (zero, zero, 0, zero, nak, Destination::Broadcast),
+            // A configured client is unicast to, even if it asked for a
+            // broadcast.
+            (zero, ciaddr, 0, ciaddr, ack, Destination::Unicast(ciaddr)),
+            (zero, ciaddr, BROADCAST_FLAG, ciaddr, ack, Destination::Unicast(ciaddr)),
+            (zero, ciaddr, 0, zero, ack, Destination::Unicast(ciaddr)),
+            (zero, zero, BROADCAST_FLAG, yiaddr, offer, Destination::Broadcast),
+            (zero, zero, BROADCAST_FLAG, yiaddr, ack, Destination::Broadcast),
+            (zero, zero, 0, yiaddr, offer, Destination::Hardware(yiaddr)),
+            (zero, zero, 0, yiaddr, ack, Destination::Hardware(yiaddr)),
+            (zero, zero, 0, zero, ack, Destination::Broadcast),
+        ];
+        for &(giaddr, ciaddr, flags, yiaddr, message_type, expected) in &cases {
+            let requ = Req::new(MessageType::DHCPREQUEST, 1).giaddr(giaddr).ciaddr(ciaddr).flags(flags).encode();
+            let requ = Message::new(&requ).unwrap();
+            let mut repl = Reply::new(&requ.header(), message_type);
+            repl.header_mut().set_yiaddr(yiaddr);
+            let dest = Destination::select(&requ.header(), &repl.header(), message_type);
+            assert_eq!(dest, expected, "{:?}", (giaddr, ciaddr, flags, yiaddr, message_type));
+        }
+    }
+
+    #[test]
+    fn destination_ports() {
+        let addr = Ipv4Addr::new(10, 0, 0, 7);
+        assert_eq!(Destination::Relay(addr).socket_addr(67), (addr, 67).into());
+        assert_eq!(Destination::Unicast(addr).socket_addr(67), (addr, 68).into());
+        assert_eq!(Destination::Hardware(addr).socket_addr(6767), (addr, 6768).into());
+        assert_eq!(Destination::Broadcast.socket_addr(67), (Ipv4Addr::BROADCAST, 68).into());
+    }
+
+    // CONFIG with two reservations, both outside the pool: one by client
+    // identifier with options of its own, one by hardware address.
+    const HOSTS: &str = r#"
+        [[subnet.host]]
+        client_identifier = "01:02:00:00:00:00:09"
+        address = "10.0.0.20"
+        host_name = "printer"
+        next_server = "10.0.0.5"
+        boot_file = "pxelinux.0"
+        options = { domain_name = "printers.example.org", lease_time = 86400 }
+        [[subnet.host]]
+        hardware_address = "02:00:00:00:00:02"
+        address = "10.0.0.30"
+    "#;
+
+    fn hosts_config() -> String {
+        // The hosts go to the first subnet.
+        let (first, second) = CONFIG.split_at(CONFIG.find("[[subnet]]\n        network = \"10.1.0.0/24\"").unwrap());
+        format!("{}{}{}", first, HOSTS, second)
+    }
+
+    const PRINTER_ID: [u8; 7] = [1, 2, 0, 0, 0, 0, 9];
+
+    #[test]
+    fn reservation_by_client_identifier_wins_over_hardware_address() {
+        let mut fx = Fixture::new(&hosts_config());
+        let offer = fx.send(&Req::new(MessageType::DHCPDISCOVER, 2)).unwrap();
+        assert_eq!(offer.yiaddr(), Ipv4Addr::new(10, 0, 0, 30));
+        let discover = Req::new(MessageType::DHCPDISCOVER, 2).option(Code::CLIENT_IDENTIFIER, &PRINTER_ID);
+        assert_eq!(fx.send(&discover).unwrap().yiaddr(), Ipv4Addr::new(10, 0, 0, 20));
+    }
+
+    #[test]
+    fn reserved_address_outside_the_pool_is_bound() {
+        let mut fx = Fixture::new(&hosts_config());
+        let addr = fx.bind(2);
+        assert_eq!(addr, Ipv4Addr::new(10, 0, 0, 30));
+        assert_eq!(fx.lease(addr).map(|l| (&l.client, l.state)), Some((&client(2), LeaseState::Bound)));
+        // It was never the pool's to give.
+        assert!(!fx.pool_gives(addr));
+    }
+
+    #[test]
+    fn reservation_options_override_the_subnets() {
+        let mut fx = Fixture::new(&hosts_config());
+        let discover = Req::new(MessageType::DHCPDISCOVER, 1).option(Code::CLIENT_IDENTIFIER, &PRINTER_ID);
+        let offer = fx.send(&discover).unwrap();
+        let mut opts = offer.options();
+        assert_eq!(opts.get(Code::DOMAIN_NAME), Some(&b"printers.example.org"[..]));
+        assert_eq!(opts.get(Code::HOST_NAME), Some(&b"printer"[..]));
+        assert_eq!(opts.get_lease_time(), Some(86400));
+        // What the host leaves alone comes from the subnet.
+        assert_eq!(opts.get_routers().map(|r| r.collect::<Vec<_>>()), Some(vec![SERVER]));
+        let m = offer.message();
+        assert_eq!(m.header().siaddr(), Ipv4Addr::new(10, 0, 0, 5));
+        assert_eq!(&m.header().file()[..11], b"pxelinux.0\0");
+        // Anyone else gets the subnet's.
+        let offer = fx.send(&Req::new(MessageType::DHCPDISCOVER, 3)).unwrap();
+        assert_eq!(offer.options().get(Code::DOMAIN_NAME), Some(&b"example.org"[..]));
+        assert_eq!(offer.message().header().siaddr(), Ipv4Addr::UNSPECIFIED);
+    }
+
+    #[test]
+    fn reserved_address_is_refused_to_others() {
+        let mut fx = Fixture::new(&hosts_config());
+        let reserved = Ipv4Addr::new(10, 0, 0, 30);
+        let discover = Req::new(MessageType::DHCPDISCOVER, 3).option(Code::REQUESTED_IP_ADDRESS, &reserved.octets());
+        assert_ne!(fx.send(&discover).unwrap().yiaddr(), reserved);
+        let nak = fx.send(&Req::selecting(3, SERVER, reserved)).unwrap();
+        assert_eq!(nak.message_type(), MessageType::DHCPNAK);
+        assert!(fx.lease(reserved).is_none());
+    }
+
+    #[test]
+    fn reserved_client_gets_nothing_but_its_reservation() {
+        let mut fx = Fixture::new(&hosts_config());
+        let nak = fx.send(&Req::selecting(2, SERVER, Ipv4Addr::new(10, 0, 0, 150))).unwrap();
+        assert_eq!(nak.message_type(), MessageType::DHCPNAK);
+        assert!(fx.server.leases().get_by_client(&client(2)).is_none());
+    }
+
+    // Subnet mask, router, DNS servers, domain name.
+    const CONFIGURED: [u8; 4] = [1, 3, 6, 15];
+
+    #[test]
+    fn requested_options_come_in_the_clients_order() {
+        let mut fx = Fixture::new(CONFIG);
+        let discover = Req::new(MessageType::DHCPDISCOVER, 1).option(Code::PARAMETER_REQUEST_LIST, &[15, 42, 3, 1]);
+        let offer = fx.send(&discover).unwrap();
+        // Message type, server identifier and lease time go first whether
+        // asked for or not; what we do not have is left out.
+        assert_eq!(offer.codes(), [53, 54, 51, 15, 3, 1]);
+    }
+
+    #[test]
+    fn mandatory_options_survive_a_short_list() {
+        let mut fx = Fixture::new(CONFIG);
+        let discover = Req::new(MessageType::DHCPDISCOVER, 1).option(Code::PARAMETER_REQUEST_LIST, &[1]);
+        let offer = fx.send(&discover).unwrap();
+        assert_eq!(offer.codes(), [53, 54, 51, 1]);
+        assert_eq!(offer.options().get_server_identifier(), Some(SERVER));
+        // Nothing is added that the reply did not have: INFORM gets no lease
+        // time.
+        let inform = Req::new(MessageType::DHCPINFORM, 1)
+            .ciaddr(Ipv4Addr::new(10, 0, 0, 7))
+            .option(Code::PARAMETER_REQUEST_LIST, &[3]);
+        assert_eq!(fx.send(&inform).unwrap().codes(), [53, 54, 3]);
+    }
+
+    #[test]
+    fn always_send_options_follow_the_requested_ones() {
+        let config = CONFIG.replace("domain_name = \"example.org\"", "domain_name = \"example.org\"\n        always_send = [\"domain_name_servers\"]");
+        let mut fx = Fixture::new(&config);
+        let discover = Req::new(MessageType::DHCPDISCOVER, 1).option(Code::PARAMETER_REQUEST_LIST, &[15, 1]);
+        assert_eq!(fx.send(&discover).unwrap().codes(), [53, 54, 51, 15, 1, 6]);
+    }
+
+    #[test]
+    fn no_list_gets_everything_configured() {
+        let mut fx = Fixture::new(CONFIG);
+        let codes = fx.send(&Req::new(MessageType::DHCPDISCOVER, 1)).unwrap().codes();
+        for code in [53, 54, 51].iter().chain(&CONFIGURED) {
+            assert!(codes.contains(code), "{} missing from {:?}", code, codes);
+        }
+    }
+
+    // CONFIG with a reservation and an agent pool for relay agent ports
+    // on the first subnet, whose relay agent is at 10.0.0.254.
+    const AGENTS: &str = r#"
+        [[subnet.host]]
+        circuit_id = "01:02"
+        remote_id = "aa"
+        address = "10.0.0.50"
+        [[subnet.agent_pool]]
+        circuit_id = "01:01"
+        start = "10.0.0.150"
+        end = "10.0.0.152"
+    "#;
+
+    const AGENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 254);
+
+    fn agents_config() -> String {
+        let (first, second) = CONFIG.split_at(CONFIG.find("[[subnet]]\n        network = \"10.1.0.0/24\"").unwrap());
+        format!("{}{}{}", first, AGENTS, second)
+    }
+
+    // Relay agent information with circuit ID `circuit_id` and, if given,
+    // remote ID `remote_id`.
+    fn agent_info(circuit_id: &[u8], remote_id: Option<&[u8]>) -> Vec<u8> {
+        let mut info = RelayAgentInformation::new();
+        info.push(SubCode::AGENT_CIRCUIT_ID, circuit_id).unwrap();
+        if let Some(remote_id) = remote_id {
+            info.push(SubCode::AGENT_REMOTE_ID, remote_id).unwrap();
+        }
+        info.encode()
+    }
+
+    fn relayed_discover(n: u8, info: &[u8]) -> Req {
+        Req::new(MessageType::DHCPDISCOVER, n)
+            .giaddr(AGENT)
+            .option(Code::RELAY_AGENT_INFORMATION, info)
+    }
+
+    #[test]
+    fn agent_information_is_echoed_to_relays_only() {
+        let mut fx = Fixture::new(&agents_config());
+        let info = agent_info(&[9, 9], Some(b"remote"));
+        let offer = fx.send(&relayed_discover(1, &info)).unwrap();
+        assert_eq!(offer.dest, Destination::Relay(AGENT));
+        assert_eq!(offer.codes().last(), Some(&82));
+        assert_eq!(offer.options().get(Code::RELAY_AGENT_INFORMATION), Some(&info[..]));
+        let direct = Req::new(MessageType::DHCPDISCOVER, 2).option(Code::RELAY_AGENT_INFORMATION, &info);
+        let offer = fx.send(&direct).unwrap();
+        assert!(!offer.codes().contains(&82));
+    }
+
+    #[test]
+    fn reservation_by_agent_needs_every_id_and_a_relay() {
+        let mut fx = Fixture::new(&agents_config());
+        let reserved = Ipv4Addr::new(10, 0, 0, 50);
+        let both = agent_info(&[1, 2], Some(&[0xaa]));
+        assert_eq!(fx.send(&relayed_discover(1, &both)).unwrap().yiaddr(), reserved);
+        let circuit_only = agent_info(&[1, 2], None);
+        assert_ne!(fx.send(&relayed_discover(2, &circuit_only)).unwrap().yiaddr(), reserved);
+        let wrong_remote = agent_info(&[1, 2], Some(&[0xbb]));
+        assert_ne!(fx.send(&relayed_discover(3, &wrong_remote)).unwrap().yiaddr(), reserved);
+        // A client could put anything in option 82 itself.
+        let direct = Req::new(MessageType::DHCPDISCOVER, 4).option(Code::RELAY_AGENT_INFORMATION, &both);
+        assert_ne!(fx.send(&direct).unwrap().yiaddr(), reserved);
+    }
+
+    #[test]
+    fn agent_pool_serves_its_port_only() {
+        let mut fx = Fixture::new(&agents_config());
+        let range = u32::from(Ipv4Addr::new(10, 0, 0, 150))..=u32::from(Ipv4Addr::new(10, 0, 0, 152));
+        let info = agent_info(&[1, 1], None);
+        for n in 1..=3 {
+            let offer = fx.send(&relayed_discover(n, &info)).unwrap();
+            assert!(range.contains(&u32::from(offer.yiaddr())), "{}", offer.yiaddr());
+        }
+        // The port has used up its range, and does not spill into the
+        // subnet's pool.
+        assert!(fx.send(&relayed_discover(4, &info)).is_none());
+        // The range is left out of the subnet's pool.
+        for addr in range.clone() {
+            assert!(!fx.server.scopes[0].allocator.allocate(&client(9), addr.into()));
+        }
+        let other = agent_info(&[7, 7], None);
+        let offer = fx.send(&relayed_discover(5, &other)).unwrap();
+        assert!(!range.contains(&u32::from(offer.yiaddr())));
+    }
+
+    #[test]
+    fn agent_reservation_held_by_another_client_is_skipped() {
+        let mut fx = Fixture::new(&agents_config());
+        let reserved = Ipv4Addr::new(10, 0, 0, 50);
+        let info = agent_info(&[1, 2], Some(&[0xaa]));
+        let offer = fx.send(&relayed_discover(1, &info)).unwrap();
+        assert_eq!(offer.yiaddr(), reserved);
+        let request = |n| Req::selecting(n, SERVER, reserved)
+            .giaddr(AGENT)
+            .option(Code::RELAY_AGENT_INFORMATION, &info);
+        assert_eq!(fx.send(&request(1)).unwrap().message_type(), MessageType::DHCPACK);
+        // A new device behind the same port gets a pool address until the
+        // old one lets go.
+        let offer = fx.send(&relayed_discover(2, &info)).unwrap();
+        assert_eq!(offer.message_type(), MessageType::DHCPOFFER);
+        assert_ne!(offer.yiaddr(), reserved);
+        let nak = fx.send(&request(2)).unwrap();
+        assert_eq!(nak.message_type(), MessageType::DHCPNAK);
+        assert_eq!(fx.lease(reserved).map(|l| &l.client), Some(&client(1)));
+        let release = Req::new(MessageType::DHCPRELEASE, 1)
+            .ciaddr(reserved)
+            .option(Code::SERVER_IDENTIFIER, &SERVER.octets());
+        assert!(fx.send(&release).is_none());
+        assert_eq!(fx.send(&relayed_discover(2, &info)).unwrap().yiaddr(), reserved);
+    }
+}
@@ -0,0 +1,289 @@
+// Needs root and iproute2: sudo -E cargo test -p bhcq --test veth -- --ignored
+
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bhcq::{server, Server};
+use bhcq::config::Config;
+use bhcq::raw::RawSender;
+use dhcpv4::{message, Message, OpCode};
+use dhcpv4::options::OptionSet;
+use dhcpv4::options::end::*;
+use dhcpv4::options::message_type::*;
+
+const ETH_P_IP: u16 = 0x0800;
+const ETH_HEADER_SIZE: usize = 14;
+const UDP_HEADER_SIZE: usize = 8;
+
+fn ip(args: &[&str]) {
+    let status = Command::new("ip").args(args).status().expect("cannot run ip");
+    assert!(status.success(), "ip {} failed", args.join(" "));
+}
+
+// A network namespace for the client, deleted again when the test ends,
+// however it ends.
+struct Netns(String);
+
+impl Netns {
+    fn new(name: String) -> Self {
+        let _ = Command::new("ip").args(["netns", "del", &name]).stderr(Stdio::null()).status();
+        ip(&["netns", "add", &name]);
+        Netns(name)
+    }
+
+    // Runs `f` on this thread inside the namespace. Sockets opened there
+    // stay in it.
+    fn enter<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let own = File::open("/proc/thread-self/ns/net").unwrap();
+        let ns = File::open(format!("/var/run/netns/{}", self.0)).unwrap();
+        assert_eq!(unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNET) }, 0, "cannot enter {}", self.0);
+        let out = f();
+        assert_eq!(unsafe { libc::setns(own.as_raw_fd(), libc::CLONE_NEWNET) }, 0, "cannot leave {}", self.0);
+        out
+    }
+}
+
+impl Drop for Netns {
+    fn drop(&mut self) {
+        let _ = Command::new("ip").args(["netns", "del", &self.0]).status();
+    }
+}
+
+// A veth pair with the server end, `name`, addressed as `address` and the
+// client end, `peer`, moved into `netns`. Deleting one end deletes both.
+struct Veth {
+    name: String,
+    peer: String,
+}
+
+impl Veth {
+    fn new(suffix: char, netns: &Netns, address: &str) -> Self {
+        let id = std::process::id() % 100_000;
+        let veth = Self {
+            name: format!("bhcq{}{}a", id, suffix),
+            peer: format!("bhcq{}{}b", id, suffix),
+        };
+        ip(&["link", "add", &veth.name, "type", "veth", "peer", "name", &veth.peer]);
+        ip(&["link", "set", &veth.peer, "netns", &netns.0]);
+        ip(&["addr", "add", address, "dev", &veth.name]);
+        ip(&["link", "set", &veth.name, "up"]);
+        ip(&["netns", "exec", &netns.0, "ip", "link", "set", &veth.peer, "up"]);
+        veth
+    }
+}
+
+impl Drop for Veth {
+    fn drop(&mut self) {
+        let _ = Command::new("ip").args(["link", "del", &self.name]).status();
+    }
+}
+
+// An AF_PACKET socket that sees the IPv4 frames arriving on one interface.
+struct Capture(libc::c_int);
+
+impl Capture {
+    fn open(ifname: &str) -> io::Result<Self> {
+        let c_ifname = CString::new(ifname).unwrap();
+        let ifindex = unsafe { libc::if_nametoindex(c_ifname.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, libc::c_int::from(ETH_P_IP.to_be())) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let capture = Capture(fd);
+        let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as libc::c_ushort;
+        addr.sll_protocol = ETH_P_IP.to_be();
+        addr.sll_ifindex = ifindex as libc::c_int;
+        let res = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let timeout = libc::timeval { tv_sec: 1, tv_usec: 0 };
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(capture)
+    }
+
+    fn recv(&self) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; 2048];
+        let read = unsafe { libc::recv(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buf.truncate(read as usize);
+        Ok(buf)
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+// A UDP datagram pulled out of an Ethernet frame.
+struct Datagram {
+    dst_mac: [u8; 6],
+    src_mac: [u8; 6],
+    dst: (Ipv4Addr, u16),
+    payload: Vec<u8>,
+}
+
+impl Datagram {
+    fn parse(frame: &[u8]) -> Option<Self> {
+        let ip = frame.get(ETH_HEADER_SIZE..)?;
+        if frame[12..14] != ETH_P_IP.to_be_bytes() || ip.len() < 20 || ip[9] != libc::IPPROTO_UDP as u8 {
+            return None;
+        }
+        let udp = ip.get(usize::from(ip[0] & 0x0f) * 4..)?;
+        let mut dst_mac = [0u8; 6];
+        let mut src_mac = [0u8; 6];
+        dst_mac.copy_from_slice(&frame[..6]);
+        src_mac.copy_from_slice(&frame[6..12]);
+        Some(Datagram {
+            dst_mac,
+            src_mac,
+            dst: (Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]), u16::from_be_bytes([udp[2], udp[3]])),
+            payload: udp.get(UDP_HEADER_SIZE..)?.to_vec(),
+        })
+    }
+}
+
+fn discover(mac: [u8; 6]) -> Vec<u8> {
+    let mut bldr = message::Builder::new();
+    {
+        let mut hdr = bldr.header_mut();
+        hdr.set_op_code(OpCode::BOOTREQUEST);
+        hdr.set_htype(1);
+        hdr.set_hlen(6);
+        hdr.set_xid(0x0bad_cafe);
+        hdr.chaddr()[..6].copy_from_slice(&mac);
+    }
+    {
+        let mut opts = bldr.options_builder();
+        opts.add_magic_cookie();
+        opts.add_message_type(MessageType::DHCPDISCOVER);
+        opts.add_end();
+    }
+    bldr.finish_owned().unwrap()
+}
+
+// Serves `veth` from a thread of its own, through a raw sender if `raw`.
+// The thread runs until the link goes away.
+fn serve(veth: &Veth, subnet: u8, port: u16, raw: bool) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bhcq-veth-test-{}", veth.name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+    let path = dir.join("bhcq.toml");
+    fs::write(&path, format!(r#"
+        lease_file = {:?}
+        port = {}
+        [[interface]]
+        name = "{}"
+        server_identifier = "10.44.{}.1"
+        raw_socket = {}
+        [[subnet]]
+        network = "10.44.{}.0/24"
+        interface = "{}"
+        pool = {{ start = "10.44.{}.100", end = "10.44.{}.199" }}
+    "#, dir.join("leases"), port, veth.name, subnet, raw, subnet, veth.name, subnet, subnet)).unwrap();
+    let config = Config::load(&path).unwrap();
+    let name = veth.name.clone();
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let res = rt.block_on(async {
+            let sock = server::bind(CString::new(name.clone())?, config.port)?;
+            let mut builder = Server::builder(config.clone()).socket(&name, sock);
+            if raw {
+                builder = builder.raw_sender(&name, RawSender::open(&name)?);
+            }
+            builder.build()?.run().await
+        });
+        if let Err(e) = res {
+            eprintln!("{}: server stopped: {}", name, e);
+        }
+    });
+    dir
+}
+
+// Broadcasts a DISCOVER from the client end and checks the OFFER that comes
+// back to it, as it arrived on the wire.
+fn offer_for_client(suffix: char, subnet: u8, port: u16, raw: bool) {
+    let netns = Netns::new(format!("bhcq{}{}", std::process::id() % 100_000, suffix));
+    let veth = Veth::new(suffix, &netns, &format!("10.44.{}.1/24", subnet));
+    let server_mac = RawSender::open(&veth.name).unwrap().mac();
+    let dir = serve(&veth, subnet, port, raw);
+    let (client, capture) = netns.enter(|| (RawSender::open(&veth.peer).unwrap(), Capture::open(&veth.peer).unwrap()));
+    let client_mac = client.mac();
+    let request = discover(client_mac);
+
+    // The server and the link may take a moment to come up, so keep asking
+    // until an offer shows up or we give up.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let offer = 'outer: loop {
+        assert!(Instant::now() < deadline, "no offer arrived on {}", veth.peer);
+        client.send_to(&request, (Ipv4Addr::UNSPECIFIED, port + 1), (Ipv4Addr::BROADCAST, port), [0xff; 6]).unwrap();
+        loop {
+            match capture.recv() {
+                Ok(frame) => match Datagram::parse(&frame) {
+                    Some(datagram) if datagram.dst.1 == port + 1 && datagram.src_mac == server_mac => break 'outer datagram,
+                    _ => continue,
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => panic!("cannot receive on {}: {}", veth.peer, e),
+            }
+        }
+    };
+    let m = Message::new(&offer.payload).unwrap();
+    let opts = OptionSet::from_message(&m).unwrap();
+    assert_eq!(opts.get_message_type(), Some(MessageType::DHCPOFFER));
+    assert_eq!(m.header().chaddr()[..6], client_mac);
+    let yiaddr = m.header().yiaddr();
+    assert!(yiaddr.octets()[..3] == [10, 44, subnet] && yiaddr.octets()[3] >= 100, "{}", yiaddr);
+    // Unicast to the client's hardware address and the address it is
+    // being offered, although it cannot answer ARP for it yet.
+    assert_eq!(offer.dst_mac, client_mac);
+    assert_eq!(offer.dst, (yiaddr, port + 1));
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+#[ignore]
+fn offer_reaches_client_through_raw_sender() {
+    offer_for_client('r', 1, 6767, true);
+}
+
+#[test]
+#[ignore]
+fn offer_reaches_client_through_arp_entry() {
+    offer_for_client('k', 2, 6867, false);
+}
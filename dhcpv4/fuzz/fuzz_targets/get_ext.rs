@@ -0,0 +1,62 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use dhcpv4::option::Code;
+use dhcpv4::options::{self, OptionMap, OptionSet, Options};
+use dhcpv4::options::{
+    bytes::*,
+    client_identifier::*,
+    domain_name::*,
+    host_name::*,
+    ip::*,
+    ips::*,
+    lease_time::*,
+    maximum_dhcp_message_size::*,
+    message_type::*,
+    parameter_request_list::*,
+    relay_agent_information::*,
+    requested_ip_address::*,
+    routers::*,
+    server_identifier::*,
+    subnet_mask::*,
+};
+// Named like the trait in `domain_name`.
+use dhcpv4::options::domain_name_servers::GetDomainNameExt as GetDomainNameServersExt;
+
+// Calls every accessor on `map`. `code` picks the option the generic ones
+// look up.
+fn get_all<M: OptionMap>(map: &mut M, code: Code) {
+    let _ = map.get_bytes(code);
+    let _ = map.get_ip(code);
+    let _ = map.get_ips(code).map(Iterator::count);
+    let _ = map.get_client_identifier();
+    let _ = map.get_domain_name();
+    let _ = map.get_domain_name_servers().map(Iterator::count);
+    let _ = map.get_host_name();
+    let _ = map.get_lease_time();
+    let _ = map.get_maximum_dhcp_message_size();
+    let _ = map.get_message_type();
+    let _ = map.get_parameter_request_list().map(Iterator::count);
+    let _ = map.get_relay_agent_information();
+    let _ = map.get_requested_ip_address();
+    let _ = map.get_routers().map(Iterator::count);
+    let _ = map.get_server_identifier();
+    let _ = map.get_subnet_mask();
+}
+
+// The first byte picks a code; the rest is taken as the options following
+// the magic cookie.
+fuzz_target!(|data: &[u8]| {
+    let (&code, data) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let mut buf = Options::<()>::MAGIC_COOKIE.to_vec();
+    buf.extend_from_slice(data);
+    buf.resize(buf.len().max(Options::<()>::MIN_SIZE), 0);
+    let opts = Options::new(&buf[..]).unwrap();
+    let mut map = options::concatenate(opts.try_iter().unwrap());
+    get_all(&mut map, Code(code));
+    let mut set: OptionSet = opts.try_iter().unwrap().collect();
+    get_all(&mut set, Code(code));
+});
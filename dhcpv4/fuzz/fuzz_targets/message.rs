@@ -0,0 +1,30 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use dhcpv4::Message;
+use dhcpv4::options::{DhcpOption, OptionSet};
+use dhcpv4::parser::{Mode, Parser};
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(m) = Message::new(data) {
+        let hdr = m.header();
+        let _ = (hdr.op_code(), hdr.htype(), hdr.hlen(), hdr.hops(), hdr.xid(), hdr.secs(), hdr.flags());
+        let _ = (hdr.ciaddr(), hdr.yiaddr(), hdr.siaddr(), hdr.giaddr(), hdr.chaddr(), hdr.sname(), hdr.file());
+        let _ = m.options().try_iter().map(Iterator::count);
+        let _ = m.try_iter_options().map(Iterator::count);
+    }
+    let strict = Message::parse(data);
+    let lenient = Parser::new(Mode::Lenient).parse(data);
+    // Lenient parsing accepts everything strict parsing does.
+    assert!(strict.is_err() || lenient.is_ok());
+    if let Ok((m, _)) = lenient {
+        let _ = m.header().chaddr();
+        let set = OptionSet::from_message(&m).expect("parsed message with a bad cookie");
+        for (code, value) in set.iter() {
+            if let Some(opt) = DhcpOption::parse(code, value) {
+                assert_eq!(opt.code(), code);
+                assert_eq!(opt.encode(), value);
+            }
+        }
+    }
+});
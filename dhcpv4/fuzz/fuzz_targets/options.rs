@@ -0,0 +1,21 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use dhcpv4::options::{self, Options};
+
+fuzz_target!(|data: &[u8]| {
+    let opts = match Options::new(data) {
+        Some(opts) => opts,
+        None => return,
+    };
+    let _ = opts.magic_cookie();
+    if let Some(iter) = opts.try_iter() {
+        for opt in iter {
+            let _ = opt.code();
+            if let Some(value) = opt.value() {
+                let _ = (value.len(), value.value());
+            }
+        }
+        let _ = options::concatenate(opts.try_iter().unwrap());
+    }
+});
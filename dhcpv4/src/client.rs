@@ -0,0 +1,796 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use super::message::{self, Header, Message, TooLarge};
+use super::op_code::OpCode;
+use super::option::Code;
+use super::options::{OptionMap, OptionSet};
+use super::options::{
+    client_identifier::*,
+    end::*,
+    lease_time::*,
+    message_type::*,
+    parameter_request_list::*,
+    requested_ip_address::*,
+    server_identifier::*,
+};
+
+// The client states of RFC 2131, figure 5.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Init,
+    Selecting,
+    Requesting,
+    Bound,
+    Renewing,
+    Rebinding,
+    InitReboot,
+    Rebooting,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub address: Ipv4Addr,
+    pub server_identifier: Ipv4Addr,
+    // None for an infinite lease.
+    pub lease_time: Option<Duration>,
+    pub renew_time: Option<Duration>,
+    pub rebinding_time: Option<Duration>,
+    // When the request that got the lease was sent, from which the times
+    // above count.
+    pub start: Instant,
+    // Every option of the DHCPACK.
+    pub options: OptionSet,
+}
+
+impl Lease {
+    fn deadline(&self, time: Option<Duration>) -> Option<Instant> {
+        time.map(|time| self.start + time)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transmit {
+    pub packet: Vec<u8>,
+    // None to broadcast.
+    pub destination: Option<Ipv4Addr>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    // Send this message to a server, on port 67.
+    Transmit(Transmit),
+    // Configure the interface with this lease, newly acquired or extended.
+    Bound(Lease),
+    // The lease was refused or ran out; stop using its address.
+    Lost(Lease),
+}
+
+// The address asked for in REQUESTING or REBOOTING, and in REQUESTING the
+// server that offered it.
+struct Offer {
+    address: Ipv4Addr,
+    server_identifier: Option<Ipv4Addr>,
+}
+
+struct Request {
+    ciaddr: Ipv4Addr,
+    requested: Option<Ipv4Addr>,
+    server_identifier: Option<Ipv4Addr>,
+    // Unicast to the server while renewing, broadcast otherwise.
+    destination: Option<Ipv4Addr>,
+}
+
+// A DHCP client that does no I/O itself. Feed it the messages received on
+// port 68 with `handle` and call `handle_timeout` once `poll_timeout` has
+// passed; act on what `poll_event` returns.
+pub struct Client {
+    chaddr: Vec<u8>,
+    client_identifier: Option<Vec<u8>>,
+    parameter_request_list: Vec<Code>,
+    state: State,
+    xid: u32,
+    // When the current exchange started, for the `secs` field.
+    started: Instant,
+    // When the last request was sent.
+    sent: Instant,
+    offer: Option<Offer>,
+    lease: Option<Lease>,
+    // Retransmission, renewal or expiry, whichever comes next.
+    timeout: Option<Instant>,
+    interval: Duration,
+    attempts: u32,
+    events: VecDeque<Event>,
+}
+
+impl Client {
+    const INITIAL_INTERVAL: Duration = Duration::from_secs(4);
+    const MAX_INTERVAL: Duration = Duration::from_secs(64);
+    // Retransmission interval while renewing or rebinding (RFC 2131,
+    // section 4.4.5).
+    const MIN_LEASE_INTERVAL: Duration = Duration::from_secs(60);
+    // DHCPREQUESTs sent in REQUESTING or REBOOTING before starting over.
+    const MAX_REQUESTS: u32 = 4;
+    // How long to wait after a DHCPDECLINE before starting over (RFC 2131,
+    // section 3.1).
+    const DECLINE_WAIT: Duration = Duration::from_secs(10);
+
+    // `xid` is the transaction ID of the first exchange; later exchanges
+    // count up from it.
+    pub fn new(chaddr: &[u8], xid: u32, now: Instant) -> Self {
+        Self {
+            chaddr: chaddr[..chaddr.len().min(16)].to_vec(),
+            client_identifier: None,
+            parameter_request_list: Vec::new(),
+            state: State::Init,
+            xid,
+            started: now,
+            sent: now,
+            offer: None,
+            lease: None,
+            timeout: None,
+            interval: Self::INITIAL_INTERVAL,
+            attempts: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    // Fails, keeping the identifier set before, if the client's messages
+    // would no longer fit in the default maximum message size.
+    pub fn set_client_identifier(&mut self, client_identifier: &[u8]) -> Result<(), TooLarge> {
+        let old = self.client_identifier.replace(client_identifier.to_vec());
+        let res = self.check_size();
+        if res.is_err() {
+            self.client_identifier = old;
+        }
+        res
+    }
+
+    // Fails like `set_client_identifier`.
+    pub fn set_parameter_request_list(&mut self, codes: &[Code]) -> Result<(), TooLarge> {
+        let old = std::mem::replace(&mut self.parameter_request_list, codes.to_vec());
+        let res = self.check_size();
+        if res.is_err() {
+            self.parameter_request_list = old;
+        }
+        res
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn lease(&self) -> Option<&Lease> {
+        self.lease.as_ref()
+    }
+
+    // Remembers `address`, acquired earlier, so that `start` asks to keep
+    // using it rather than looking for a new lease.
+    pub fn reboot(&mut self, address: Ipv4Addr) {
+        self.state = State::InitReboot;
+        self.offer = Some(Offer { address, server_identifier: None });
+    }
+
+    // Broadcasts a DHCPDISCOVER, or from INIT-REBOOT a DHCPREQUEST.
+    pub fn start(&mut self, now: Instant) {
+        if self.state == State::InitReboot {
+            self.new_exchange(now);
+            self.state = State::Rebooting;
+            self.send_request(now);
+        } else {
+            self.restart(now);
+        }
+    }
+
+    // Gives the lease back to its server and returns to INIT.
+    pub fn release(&mut self, now: Instant) {
+        if let Some(lease) = self.lease.take() {
+            self.new_exchange(now);
+            let packet = self.build(MessageType::DHCPRELEASE, lease.address, now, |opts| {
+                opts.add_server_identifier(lease.server_identifier);
+            });
+            self.transmit(packet, Some(lease.server_identifier));
+        }
+        self.state = State::Init;
+        self.timeout = None;
+    }
+
+    // Tells the server the leased address is already in use, for instance
+    // as found by ARP, and starts over ten seconds later.
+    pub fn decline(&mut self, now: Instant) {
+        if let Some(lease) = self.lease.take() {
+            let packet = self.build(MessageType::DHCPDECLINE, Ipv4Addr::UNSPECIFIED, now, |opts| {
+                opts.add_requested_ip_address(lease.address);
+                opts.add_server_identifier(lease.server_identifier);
+            });
+            self.transmit(packet, None);
+        }
+        self.offer = None;
+        self.state = State::Init;
+        self.timeout = Some(now + Self::DECLINE_WAIT);
+    }
+
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        self.timeout
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) {
+        match self.timeout {
+            Some(timeout) if timeout <= now => {},
+            _ => return,
+        }
+        match self.state {
+            State::Selecting => {
+                self.send_discover(now);
+            },
+            State::Requesting | State::Rebooting => {
+                if self.attempts >= Self::MAX_REQUESTS {
+                    self.restart(now);
+                } else {
+                    self.send_request(now);
+                }
+            },
+            State::Bound => {
+                self.state = State::Renewing;
+                self.new_exchange(now);
+                self.send_request(now);
+            },
+            State::Renewing => {
+                if self.passed(|lease| lease.rebinding_time, now) {
+                    self.state = State::Rebinding;
+                    self.new_exchange(now);
+                }
+                self.send_request(now);
+            },
+            State::Rebinding => {
+                if self.passed(|lease| lease.lease_time, now) {
+                    if let Some(lease) = self.lease.take() {
+                        self.events.push_back(Event::Lost(lease));
+                    }
+                    self.restart(now);
+                } else {
+                    self.send_request(now);
+                }
+            },
+            // Only `decline` leaves a timeout set in INIT.
+            State::Init => self.restart(now),
+            State::InitReboot => self.timeout = None,
+        }
+    }
+
+    // Processes a message received on port 68. Messages for other clients
+    // or transactions, and those that make no sense in the current state,
+    // are ignored.
+    pub fn handle(&mut self, bytes: &[u8], now: Instant) {
+        let m = match Message::parse(bytes) {
+            Ok(m) => m,
+            Err(_) => return,
+        };
+        let hdr = m.header();
+        if hdr.op_code() != OpCode::BOOTREPLY || hdr.xid() != self.xid || !hdr.chaddr().starts_with(&self.chaddr) {
+            return;
+        }
+        let opts = match OptionSet::from_message(&m) {
+            Some(opts) => opts,
+            None => return,
+        };
+        let server_identifier = opts.get_server_identifier();
+        match (self.state, opts.get_message_type()) {
+            (State::Selecting, Some(MessageType::DHCPOFFER)) => {
+                if let Some(server_identifier) = server_identifier {
+                    self.offer = Some(Offer { address: hdr.yiaddr(), server_identifier: Some(server_identifier) });
+                    self.state = State::Requesting;
+                    self.attempts = 0;
+                    self.interval = Self::INITIAL_INTERVAL;
+                    self.send_request(now);
+                }
+            },
+            (State::Requesting, Some(MessageType::DHCPACK))
+            | (State::Rebooting, Some(MessageType::DHCPACK))
+            | (State::Renewing, Some(MessageType::DHCPACK))
+            | (State::Rebinding, Some(MessageType::DHCPACK)) => {
+                if let Some(lease) = self.lease_from_ack(&hdr, opts) {
+                    self.bind(lease);
+                }
+            },
+            (State::Requesting, Some(MessageType::DHCPNAK))
+            | (State::Rebooting, Some(MessageType::DHCPNAK))
+            | (State::Renewing, Some(MessageType::DHCPNAK))
+            | (State::Rebinding, Some(MessageType::DHCPNAK)) => {
+                if let Some(lease) = self.lease.take() {
+                    self.events.push_back(Event::Lost(lease));
+                }
+                self.restart(now);
+            },
+            _ => {},
+        }
+    }
+
+    fn lease_from_ack(&self, hdr: &Header<&[u8]>, mut opts: OptionSet) -> Option<Lease> {
+        let server_identifier = opts.get_server_identifier()
+            .or_else(|| self.lease.as_ref().map(|lease| lease.server_identifier))?;
+        let lease_time = match opts.get_lease_time()? {
+            u32::MAX => None,
+            secs => Some(Duration::from_secs(secs.into())),
+        };
+        let time = |code, default: fn(Duration) -> Duration| {
+            let lease_time = lease_time?;
+            let time = opts.get_option(code)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(|bytes| Duration::from_secs(u32::from_be_bytes(bytes).into()))
+                .filter(|&t| t <= lease_time)
+                .unwrap_or_else(|| default(lease_time));
+            Some(time)
+        };
+        // Defaults from RFC 2131, section 4.4.5.
+        let rebinding_time = time(Code::REBINDING_TIME_VALUE, |t| t * 7 / 8);
+        let renew_time = time(Code::RENEW_TIME_VALUE, |t| t / 2)
+            .map(|t| rebinding_time.map_or(t, |rebind| t.min(rebind)));
+        Some(Lease {
+            address: hdr.yiaddr(),
+            server_identifier,
+            lease_time,
+            renew_time,
+            rebinding_time,
+            start: self.sent,
+            options: opts,
+        })
+    }
+
+    fn bind(&mut self, lease: Lease) {
+        self.state = State::Bound;
+        self.offer = None;
+        self.timeout = lease.deadline(lease.renew_time);
+        self.lease = Some(lease.clone());
+        self.events.push_back(Event::Bound(lease));
+    }
+
+    // Whether the time `f` picks from the lease has passed.
+    fn passed<F: Fn(&Lease) -> Option<Duration>>(&self, f: F, now: Instant) -> bool {
+        match &self.lease {
+            Some(lease) => lease.deadline(f(lease)).is_some_and(|deadline| deadline <= now),
+            None => true,
+        }
+    }
+
+    fn restart(&mut self, now: Instant) {
+        self.offer = None;
+        self.new_exchange(now);
+        self.state = State::Selecting;
+        self.send_discover(now);
+    }
+
+    fn new_exchange(&mut self, now: Instant) {
+        self.xid = self.xid.wrapping_add(1);
+        self.started = now;
+        self.attempts = 0;
+        self.interval = Self::INITIAL_INTERVAL;
+    }
+
+    fn send_discover(&mut self, now: Instant) {
+        let packet = self.build(MessageType::DHCPDISCOVER, Ipv4Addr::UNSPECIFIED, now, |_| {});
+        self.transmit(packet, None);
+        self.backoff(now);
+    }
+
+    // What goes in the DHCPREQUEST the current state calls for (RFC 2131,
+    // table 4).
+    fn request(&self) -> Option<Request> {
+        match self.state {
+            State::Requesting | State::Rebooting => {
+                let offer = self.offer.as_ref()?;
+                Some(Request {
+                    ciaddr: Ipv4Addr::UNSPECIFIED,
+                    requested: Some(offer.address),
+                    server_identifier: offer.server_identifier,
+                    destination: None,
+                })
+            },
+            State::Renewing | State::Rebinding => {
+                let lease = self.lease.as_ref()?;
+                Some(Request {
+                    ciaddr: lease.address,
+                    requested: None,
+                    server_identifier: None,
+                    destination: Some(lease.server_identifier).filter(|_| self.state == State::Renewing),
+                })
+            },
+            _ => None,
+        }
+    }
+
+    fn send_request(&mut self, now: Instant) {
+        let request = match self.request() {
+            Some(request) => request,
+            None => return,
+        };
+        let packet = self.build(MessageType::DHCPREQUEST, request.ciaddr, now, |opts| {
+            if let Some(addr) = request.requested {
+                opts.add_requested_ip_address(addr);
+            }
+            if let Some(addr) = request.server_identifier {
+                opts.add_server_identifier(addr);
+            }
+        });
+        self.transmit(packet, request.destination);
+        self.sent = now;
+        match self.state {
+            State::Renewing => self.retry_before(|lease| lease.rebinding_time, now),
+            State::Rebinding => self.retry_before(|lease| lease.lease_time, now),
+            _ => self.backoff(now),
+        }
+    }
+
+    // Doubles the retransmission interval, up to a minute or so (RFC 2131,
+    // section 4.1).
+    fn backoff(&mut self, now: Instant) {
+        self.timeout = Some(now + self.interval);
+        self.interval = (self.interval * 2).min(Self::MAX_INTERVAL);
+        self.attempts += 1;
+    }
+
+    // Retries after half the time left before the time `f` picks from the
+    // lease, but not sooner than a minute, or at that time.
+    fn retry_before<F: Fn(&Lease) -> Option<Duration>>(&mut self, f: F, now: Instant) {
+        let deadline = match &self.lease {
+            Some(lease) => lease.deadline(f(lease)),
+            None => None,
+        };
+        self.timeout = deadline.map(|deadline| {
+            let half = deadline.saturating_duration_since(now) / 2;
+            deadline.min(now + half.max(Self::MIN_LEASE_INTERVAL))
+        });
+    }
+
+    fn build<F: FnOnce(&mut OptionSet)>(&self, typ: MessageType, ciaddr: Ipv4Addr, now: Instant, f: F) -> Vec<u8> {
+        // The setters refuse whatever would make a message too large.
+        self.try_build(typ, ciaddr, now, f).expect("client message too large")
+    }
+
+    // Builds the largest message the client sends, a DHCPREQUEST in
+    // REQUESTING, to see that it fits.
+    fn check_size(&self) -> Result<(), TooLarge> {
+        self.try_build(MessageType::DHCPREQUEST, Ipv4Addr::UNSPECIFIED, self.started, |opts| {
+            opts.add_requested_ip_address(Ipv4Addr::UNSPECIFIED);
+            opts.add_server_identifier(Ipv4Addr::UNSPECIFIED);
+        }).map(|_| ())
+    }
+
+    fn try_build<F: FnOnce(&mut OptionSet)>(&self, typ: MessageType, ciaddr: Ipv4Addr, now: Instant, f: F) -> Result<Vec<u8>, TooLarge> {
+        let mut bldr = message::Builder::new();
+        let mut hdr = bldr.header_mut();
+        hdr.set_op_code(OpCode::BOOTREQUEST);
+        hdr.set_hlen(self.chaddr.len() as u8);
+        hdr.set_xid(self.xid);
+        hdr.set_secs(now.saturating_duration_since(self.started).as_secs().min(u16::MAX.into()) as u16);
+        hdr.set_ciaddr(ciaddr);
+        hdr.chaddr()[..self.chaddr.len()].copy_from_slice(&self.chaddr);
+        let mut set = OptionSet::new();
+        set.add_message_type(typ);
+        if let Some(client_identifier) = &self.client_identifier {
+            set.add_client_identifier(client_identifier);
+        }
+        f(&mut set);
+        let asks = typ == MessageType::DHCPDISCOVER || typ == MessageType::DHCPREQUEST;
+        if asks && !self.parameter_request_list.is_empty() {
+            set.add_parameter_request_list(self.parameter_request_list.iter().copied());
+        }
+        {
+            let mut opts = bldr.options_builder();
+            opts.add_magic_cookie();
+            set.write(&mut opts);
+            opts.add_end();
+        }
+        bldr.finish_owned()
+    }
+
+    fn transmit(&mut self, packet: Vec<u8>, destination: Option<Ipv4Addr>) {
+        self.events.push_back(Event::Transmit(Transmit { packet, destination }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::bytes::AddBytesExt;
+
+    const MAC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 50);
+
+    fn at(base: Instant, secs: u64) -> Instant {
+        base + Duration::from_secs(secs)
+    }
+
+    fn events(client: &mut Client) -> Vec<Event> {
+        std::iter::from_fn(|| client.poll_event()).collect()
+    }
+
+    // The one message the client has to send, with its type, xid and
+    // options.
+    fn sent(client: &mut Client) -> (Transmit, MessageType, u32, OptionSet) {
+        let mut events = events(client);
+        assert_eq!(events.len(), 1, "{:?}", events);
+        let transmit = match events.pop() {
+            Some(Event::Transmit(transmit)) => transmit,
+            event => panic!("expected a transmit, got {:?}", event),
+        };
+        let m = Message::parse(&transmit.packet).unwrap();
+        assert_eq!(m.header().op_code(), OpCode::BOOTREQUEST);
+        assert_eq!(&m.header().chaddr()[..6], &MAC);
+        let xid = m.header().xid();
+        let opts = OptionSet::from_message(&m).unwrap();
+        let typ = opts.get_message_type().unwrap();
+        (transmit, typ, xid, opts)
+    }
+
+    fn reply(xid: u32, typ: MessageType, lease_time: u32, times: &[(Code, u32)]) -> Vec<u8> {
+        let mut bldr = message::Builder::new();
+        let mut hdr = bldr.header_mut();
+        hdr.set_op_code(OpCode::BOOTREPLY);
+        hdr.set_hlen(6);
+        hdr.set_xid(xid);
+        if typ != MessageType::DHCPNAK {
+            hdr.set_yiaddr(ADDR);
+        }
+        hdr.chaddr()[..6].copy_from_slice(&MAC);
+        let mut set = OptionSet::new();
+        set.add_message_type(typ);
+        set.add_server_identifier(SERVER);
+        if typ != MessageType::DHCPNAK {
+            set.add_lease_time(lease_time);
+        }
+        for &(code, secs) in times {
+            set.add_bytes(code, &secs.to_be_bytes());
+        }
+        {
+            let mut opts = bldr.options_builder();
+            opts.add_magic_cookie();
+            set.write(&mut opts);
+            opts.add_end();
+        }
+        bldr.finish_owned().unwrap()
+    }
+
+    // Runs DORA at `now` and returns the bound client.
+    fn bound(now: Instant, lease_time: u32) -> Client {
+        let mut client = Client::new(&MAC, 0x1000, now);
+        client.start(now);
+        let (_, _, xid, _) = sent(&mut client);
+        client.handle(&reply(xid, MessageType::DHCPOFFER, lease_time, &[]), now);
+        let (_, _, xid, _) = sent(&mut client);
+        client.handle(&reply(xid, MessageType::DHCPACK, lease_time, &[]), now);
+        events(&mut client);
+        client
+    }
+
+    #[test]
+    fn dora() {
+        let now = Instant::now();
+        let mut client = Client::new(&MAC, 0x1000, now);
+        client.set_parameter_request_list(&[Code::SUBNET_MASK, Code::ROUTER]).unwrap();
+        client.start(now);
+        assert_eq!(client.state(), State::Selecting);
+        let (transmit, typ, xid, opts) = sent(&mut client);
+        assert_eq!((typ, transmit.destination), (MessageType::DHCPDISCOVER, None));
+        assert!(opts.contains(Code::PARAMETER_REQUEST_LIST));
+
+        // Replies to someone else's transaction are ignored.
+        client.handle(&reply(xid + 1, MessageType::DHCPOFFER, 1600, &[]), now);
+        assert!(events(&mut client).is_empty());
+
+        client.handle(&reply(xid, MessageType::DHCPOFFER, 1600, &[]), at(now, 1));
+        assert_eq!(client.state(), State::Requesting);
+        let (transmit, typ, request_xid, opts) = sent(&mut client);
+        assert_eq!((typ, transmit.destination, request_xid), (MessageType::DHCPREQUEST, None, xid));
+        assert_eq!(opts.get_requested_ip_address(), Some(ADDR));
+        assert_eq!(opts.get_server_identifier(), Some(SERVER));
+
+        client.handle(&reply(xid, MessageType::DHCPACK, 1600, &[]), at(now, 2));
+        assert_eq!(client.state(), State::Bound);
+        let lease = match events(&mut client).pop() {
+            Some(Event::Bound(lease)) => lease,
+            event => panic!("expected a lease, got {:?}", event),
+        };
+        assert_eq!((lease.address, lease.server_identifier), (ADDR, SERVER));
+        // Times count from the DHCPREQUEST.
+        assert_eq!(lease.start, at(now, 1));
+        assert_eq!(lease.renew_time, Some(Duration::from_secs(800)));
+        assert_eq!(lease.rebinding_time, Some(Duration::from_secs(1400)));
+        assert_eq!(client.poll_timeout(), Some(at(now, 801)));
+    }
+
+    #[test]
+    fn nak_returns_to_init() {
+        let now = Instant::now();
+        let mut client = Client::new(&MAC, 0x1000, now);
+        client.start(now);
+        let (_, _, xid, _) = sent(&mut client);
+        client.handle(&reply(xid, MessageType::DHCPOFFER, 1600, &[]), now);
+        let (_, _, xid, _) = sent(&mut client);
+        client.handle(&reply(xid, MessageType::DHCPNAK, 0, &[]), at(now, 1));
+        assert_eq!(client.state(), State::Selecting);
+        let (_, typ, new_xid, _) = sent(&mut client);
+        assert_eq!(typ, MessageType::DHCPDISCOVER);
+        assert_ne!(new_xid, xid);
+    }
+
+    #[test]
+    fn nak_while_renewing_loses_lease() {
+        let now = Instant::now();
+        let mut client = bound(now, 1600);
+        client.handle_timeout(at(now, 800));
+        let (_, _, xid, _) = sent(&mut client);
+        client.handle(&reply(xid, MessageType::DHCPNAK, 0, &[]), at(now, 801));
+        let events = events(&mut client);
+        assert!(matches!(events[0], Event::Lost(ref lease) if lease.address == ADDR));
+        assert!(matches!(events[1], Event::Transmit(_)));
+        assert_eq!(client.state(), State::Selecting);
+        assert_eq!(client.lease(), None);
+    }
+
+    #[test]
+    fn renew_then_rebind() {
+        let now = Instant::now();
+        let mut client = bound(now, 1600);
+        // Nothing happens before T1.
+        client.handle_timeout(at(now, 799));
+        assert!(events(&mut client).is_empty());
+
+        client.handle_timeout(at(now, 800));
+        assert_eq!(client.state(), State::Renewing);
+        let (transmit, typ, _, opts) = sent(&mut client);
+        assert_eq!((typ, transmit.destination), (MessageType::DHCPREQUEST, Some(SERVER)));
+        assert_eq!(Message::new(&transmit.packet).unwrap().header().ciaddr(), ADDR);
+        assert!(!opts.contains(Code::REQUESTED_IP_ADDRESS));
+        assert!(!opts.contains(Code::SERVER_IDENTIFIER));
+        // Half the time left before T2.
+        assert_eq!(client.poll_timeout(), Some(at(now, 1100)));
+        client.handle_timeout(at(now, 1100));
+        sent(&mut client);
+        assert_eq!(client.poll_timeout(), Some(at(now, 1250)));
+
+        // At T2 the request is broadcast to any server.
+        client.handle_timeout(at(now, 1400));
+        assert_eq!(client.state(), State::Rebinding);
+        let (transmit, _, xid, _) = sent(&mut client);
+        assert_eq!(transmit.destination, None);
+        assert_eq!(client.poll_timeout(), Some(at(now, 1500)));
+
+        client.handle(&reply(xid, MessageType::DHCPACK, 1600, &[(Code::RENEW_TIME_VALUE, 100), (Code::REBINDING_TIME_VALUE, 200)]), at(now, 1450));
+        assert_eq!(client.state(), State::Bound);
+        assert!(matches!(events(&mut client)[..], [Event::Bound(_)]));
+        let lease = client.lease().unwrap();
+        assert_eq!(lease.start, at(now, 1400));
+        assert_eq!(lease.renew_time, Some(Duration::from_secs(100)));
+        assert_eq!(client.poll_timeout(), Some(at(now, 1500)));
+    }
+
+    #[test]
+    fn lease_expires_while_rebinding() {
+        let now = Instant::now();
+        let mut client = bound(now, 1600);
+        let mut timeouts = Vec::new();
+        while let Some(timeout) = client.poll_timeout() {
+            if timeout >= at(now, 1600) {
+                break;
+            }
+            timeouts.push((timeout - now).as_secs());
+            client.handle_timeout(timeout);
+            sent(&mut client);
+        }
+        // Never more often than once a minute, down to the wire.
+        assert_eq!(timeouts, [800, 1100, 1250, 1325, 1385, 1400, 1500, 1560]);
+        assert_eq!(client.state(), State::Rebinding);
+        client.handle_timeout(at(now, 1600));
+        let events = events(&mut client);
+        assert!(matches!(events[0], Event::Lost(_)));
+        assert!(matches!(events[1], Event::Transmit(_)));
+        assert_eq!(client.state(), State::Selecting);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_a_minute() {
+        let now = Instant::now();
+        let mut client = Client::new(&MAC, 0x1000, now);
+        client.start(now);
+        let (_, _, xid, _) = sent(&mut client);
+        let mut elapsed = 0;
+        let mut intervals = Vec::new();
+        for _ in 0..6 {
+            let timeout = client.poll_timeout().unwrap();
+            intervals.push((timeout - now).as_secs() - elapsed);
+            elapsed = (timeout - now).as_secs();
+            // Early calls do nothing.
+            client.handle_timeout(timeout - Duration::from_secs(1));
+            assert!(events(&mut client).is_empty());
+            client.handle_timeout(timeout);
+            let (_, typ, resent_xid, _) = sent(&mut client);
+            assert_eq!((typ, resent_xid), (MessageType::DHCPDISCOVER, xid));
+        }
+        assert_eq!(intervals, [4, 8, 16, 32, 64, 64]);
+    }
+
+    #[test]
+    fn requesting_gives_up_after_a_few_tries() {
+        let now = Instant::now();
+        let mut client = Client::new(&MAC, 0x1000, now);
+        client.start(now);
+        let (_, _, xid, _) = sent(&mut client);
+        client.handle(&reply(xid, MessageType::DHCPOFFER, 1600, &[]), now);
+        sent(&mut client);
+        for _ in 1..Client::MAX_REQUESTS {
+            client.handle_timeout(client.poll_timeout().unwrap());
+            let (_, typ, _, _) = sent(&mut client);
+            assert_eq!(typ, MessageType::DHCPREQUEST);
+        }
+        client.handle_timeout(client.poll_timeout().unwrap());
+        let (_, typ, _, _) = sent(&mut client);
+        assert_eq!(typ, MessageType::DHCPDISCOVER);
+        assert_eq!(client.state(), State::Selecting);
+    }
+
+    #[test]
+    fn decline_waits_before_starting_over() {
+        let now = Instant::now();
+        let mut client = bound(now, 1600);
+        client.decline(at(now, 5));
+        let (_, typ, _, opts) = sent(&mut client);
+        assert_eq!(typ, MessageType::DHCPDECLINE);
+        assert_eq!(opts.get_requested_ip_address(), Some(ADDR));
+        assert_eq!(client.state(), State::Init);
+        assert_eq!(client.poll_timeout(), Some(at(now, 15)));
+        client.handle_timeout(at(now, 14));
+        assert!(events(&mut client).is_empty());
+        client.handle_timeout(at(now, 15));
+        let (_, typ, _, _) = sent(&mut client);
+        assert_eq!(typ, MessageType::DHCPDISCOVER);
+    }
+
+    #[test]
+    fn release_unicasts_to_server() {
+        let now = Instant::now();
+        let mut client = bound(now, 1600);
+        client.release(at(now, 5));
+        let (transmit, typ, _, _) = sent(&mut client);
+        assert_eq!((typ, transmit.destination), (MessageType::DHCPRELEASE, Some(SERVER)));
+        assert_eq!(client.state(), State::Init);
+        assert_eq!(client.poll_timeout(), None);
+    }
+
+    #[test]
+    fn reboot_asks_for_old_address() {
+        let now = Instant::now();
+        let mut client = Client::new(&MAC, 0x1000, now);
+        client.reboot(ADDR);
+        client.start(now);
+        assert_eq!(client.state(), State::Rebooting);
+        let (_, typ, xid, opts) = sent(&mut client);
+        assert_eq!(typ, MessageType::DHCPREQUEST);
+        assert_eq!(opts.get_requested_ip_address(), Some(ADDR));
+        assert!(!opts.contains(Code::SERVER_IDENTIFIER));
+        client.handle(&reply(xid, MessageType::DHCPACK, 1600, &[]), now);
+        assert_eq!(client.state(), State::Bound);
+    }
+
+    #[test]
+    fn refuses_settings_that_do_not_fit() {
+        let now = Instant::now();
+        let mut client = Client::new(&MAC, 0x1000, now);
+        client.set_client_identifier(&[1, 2, 3]).unwrap();
+        assert!(client.set_client_identifier(&[1; 600]).is_err());
+        let codes: Vec<_> = (1..=255).map(Code).collect();
+        client.set_parameter_request_list(&codes).unwrap();
+        assert!(client.set_client_identifier(&[1; 255]).is_err());
+        client.start(now);
+        let (_, _, _, opts) = sent(&mut client);
+        assert_eq!(opts.get_client_identifier(), Some(&[1, 2, 3][..]));
+        assert_eq!(opts.get_parameter_request_list().map(Iterator::count), Some(255));
+    }
+}
@@ -0,0 +1,60 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use super::option::Code;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    // Not even the fixed-size header is there.
+    ShortHeader,
+    // Shorter than `Message::MIN_SIZE`.
+    ShortMessage,
+    BadMagicCookie,
+    // The option runs past the end of the field holding it.
+    TruncatedOption(Code),
+    // The option's value (all instances joined) has a length the option
+    // cannot have.
+    LengthMismatch(Code, usize),
+    // A field holding options does not end with END.
+    MissingEnd,
+    // Something other than padding follows END.
+    TrailingGarbage,
+}
+
+// What is wrong with a message, and the byte offset into the message where
+// the problem starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Error {
+    kind: ErrorKind,
+    offset: usize,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, offset: usize) -> Self {
+        Self { kind, offset }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ErrorKind::ShortHeader => write!(f, "header cut short at byte {}", self.offset),
+            ErrorKind::ShortMessage => write!(f, "message cut short at byte {}", self.offset),
+            ErrorKind::BadMagicCookie => write!(f, "bad magic cookie at byte {}", self.offset),
+            ErrorKind::TruncatedOption(Code(code)) => write!(f, "option {} at byte {} is truncated", code, self.offset),
+            ErrorKind::LengthMismatch(Code(code), len) => write!(f, "option {} at byte {} cannot be {} bytes long", code, self.offset, len),
+            ErrorKind::MissingEnd => write!(f, "no END option before byte {}", self.offset),
+            ErrorKind::TrailingGarbage => write!(f, "garbage after END at byte {}", self.offset),
+        }
+    }
+}
+
+impl StdError for Error {}
@@ -5,3 +5,9 @@ pub use message::Message;
 pub mod options;
 pub mod option;
 pub use option::Option;
+pub mod error;
+pub use error::Error;
+pub mod parser;
+pub use parser::Parser;
+pub mod client;
+pub use client::Client;
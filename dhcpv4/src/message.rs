@@ -1,12 +1,24 @@
+use std::error::Error as StdError;
+use std::fmt;
 use std::net::Ipv4Addr;
 
+use super::error::{Error, ErrorKind};
 use super::op_code::OpCode;
-use super::options;
+use super::option::{self, Code};
+use super::options::{self, DhcpOption};
+use super::parser::{Mode, Quirks};
 
 pub struct Message<B>(B);
 
 impl<T> Message<T> {
     pub const MIN_SIZE: usize = 300;
+    // Every client accepts messages of this size: the header and an options
+    // field of 312 bytes (RFC 2131, section 2), which is 576 bytes once in
+    // IP and UDP (RFC 2132, section 9.10).
+    pub const DEFAULT_MAX_SIZE: usize = 548;
+    // MAXIMUM_DHCP_MESSAGE_SIZE counts the IP and UDP headers as well
+    // (RFC 2132, section 9.10).
+    pub const IP_UDP_HEADER_SIZE: usize = 28;
 }
 
 impl<'a> Message<&'a [u8]> {
@@ -18,6 +30,14 @@ impl<'a> Message<&'a [u8]> {
         None
     }
 
+    // Like `new`, but checks the options as well: the magic cookie, that
+    // every option fits in its field and has a length its code allows, and
+    // that every field holding options ends with END followed by nothing
+    // but padding. See `parser::Parser` for a more forgiving parse.
+    pub fn parse(buf: &'a [u8]) -> Result<Self, Error> {
+        check(buf, Mode::Strict).map(|(msg, _)| msg)
+    }
+
     #[inline]
     pub fn as_slice(&self) -> &'a [u8] {
         self.0
@@ -25,12 +45,183 @@ impl<'a> Message<&'a [u8]> {
 
     #[inline]
     pub fn header(&self) -> Header<&[u8]> {
-        Header::<&_>::new(&self.as_slice()[..Header::<()>::SIZE]).unwrap()
+        // Every constructor checks that the header is complete.
+        Header(&self.as_slice()[..Header::<()>::SIZE])
     }
 
     #[inline]
     pub fn options(&self) -> options::Options<&[u8]> {
-        options::Options::new(&self.as_slice()[Header::<()>::SIZE..]).unwrap()
+        // Not `Options::new`: a leniently parsed message may have a short
+        // options field.
+        options::Options(&self.as_slice()[Header::<()>::SIZE..])
+    }
+
+    // Iterates over every option of the message: those in the options
+    // field, then those in the `file` and `sname` fields if the OVERLOAD
+    // option says they hold options (RFC 2132, section 9.3). Padding and
+    // END are skipped. None if the magic cookie is wrong.
+    pub fn try_iter_options(&self) -> Option<OptionsIter<'a>> {
+        let buf = self.as_slice();
+        let opts = &buf[Header::<()>::SIZE..];
+        if opts.get(..4) != Some(&options::Options::<()>::MAGIC_COOKIE[..]) {
+            return None;
+        }
+        Some(OptionsIter {
+            buf,
+            area: Area::Options,
+            rest: &opts[4..],
+            overload: 0,
+        })
+    }
+}
+
+// Validates `buf` as a message. A lenient parse counts quirks in the
+// returned `Quirks` where a strict one fails.
+pub(crate) fn check(buf: &[u8], mode: Mode) -> Result<(Message<&[u8]>, Quirks), Error> {
+    let mut quirks = Quirks::default();
+    let cookie = Header::<()>::SIZE;
+    if buf.len() < cookie {
+        return Err(Error::new(ErrorKind::ShortHeader, buf.len()));
+    }
+    if buf.len() < Message::<()>::MIN_SIZE {
+        if mode == Mode::Strict || buf.len() < cookie + 4 {
+            return Err(Error::new(ErrorKind::ShortMessage, buf.len()));
+        }
+        quirks.short_message = 1;
+    }
+    if buf[cookie..cookie + 4] != options::Options::<()>::MAGIC_COOKIE {
+        return Err(Error::new(ErrorKind::BadMagicCookie, cookie));
+    }
+    let mut check = FieldCheck { buf, mode, quirks, lengths: Vec::new() };
+    let overload = check.field(cookie + 4, buf.len())?;
+    if overload & OptionsIter::OVERLOAD_FILE != 0 {
+        check.field(108, 108 + 128)?;
+    }
+    if overload & OptionsIter::OVERLOAD_SNAME != 0 {
+        check.field(44, 44 + 64)?;
+    }
+    for &(code, offset, len) in &check.lengths {
+        if !DhcpOption::valid_len(code, len) {
+            if mode == Mode::Strict {
+                return Err(Error::new(ErrorKind::LengthMismatch(code, len), offset));
+            }
+            check.quirks.bad_length = 1;
+        }
+    }
+    Ok((Message(buf), check.quirks))
+}
+
+struct FieldCheck<'a> {
+    buf: &'a [u8],
+    mode: Mode,
+    quirks: Quirks,
+    // Each code seen, where it first appears and its total length.
+    lengths: Vec<(Code, usize, usize)>,
+}
+
+impl<'a> FieldCheck<'a> {
+    // Walks the options in `buf[start..end]`, adding up their lengths.
+    // Returns the value of the OVERLOAD option if there is one.
+    fn field(&mut self, start: usize, end: usize) -> Result<u8, Error> {
+        let buf = &self.buf[..end];
+        let mut pos = start;
+        let mut overload = 0;
+        while pos < end {
+            let code = Code(buf[pos]);
+            match code {
+                Code::PAD => pos += 1,
+                Code::END => {
+                    if let Some(garbage) = buf[pos + 1..].iter().position(|&b| b != 0) {
+                        if self.mode == Mode::Strict {
+                            return Err(Error::new(ErrorKind::TrailingGarbage, pos + 1 + garbage));
+                        }
+                        self.quirks.trailing_garbage = 1;
+                    }
+                    return Ok(overload);
+                },
+                _ => {
+                    let truncated = Error::new(ErrorKind::TruncatedOption(code), pos);
+                    let len = *buf.get(pos + 1).ok_or(truncated)? as usize;
+                    let value = buf.get(pos + 2..pos + 2 + len).ok_or(truncated)?;
+                    if code == Code::OVERLOAD && start == Header::<()>::SIZE + 4 {
+                        overload = value.first().copied().unwrap_or(0);
+                    }
+                    match self.lengths.iter_mut().find(|(c, _, _)| *c == code) {
+                        Some((_, _, total)) => *total += len,
+                        None => self.lengths.push((code, pos, len)),
+                    }
+                    pos += 2 + len;
+                },
+            }
+        }
+        if self.mode == Mode::Strict {
+            return Err(Error::new(ErrorKind::MissingEnd, end));
+        }
+        self.quirks.missing_end = 1;
+        Ok(overload)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Area {
+    Options,
+    File,
+    Sname,
+}
+
+pub struct OptionsIter<'a> {
+    buf: &'a [u8],
+    area: Area,
+    rest: &'a [u8],
+    overload: u8,
+}
+
+impl<'a> OptionsIter<'a> {
+    const OVERLOAD_FILE: u8 = 1;
+    const OVERLOAD_SNAME: u8 = 2;
+
+    // Moves on to the next field holding options, if any.
+    fn next_area(&mut self) -> bool {
+        let next = match self.area {
+            Area::Options if self.overload & Self::OVERLOAD_FILE != 0 => Area::File,
+            Area::Options | Area::File if self.overload & Self::OVERLOAD_SNAME != 0 => Area::Sname,
+            _ => return false,
+        };
+        self.area = next;
+        self.rest = match next {
+            Area::File => &self.buf[108..108 + 128],
+            _ => &self.buf[44..44 + 64],
+        };
+        true
+    }
+}
+
+impl<'a> Iterator for OptionsIter<'a> {
+    type Item = option::Option<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // A truncated option ends its field like END does.
+            if let Some((opt, rest)) = option::Option::read(self.rest) {
+                self.rest = rest;
+                match opt.code() {
+                    Code::PAD => continue,
+                    Code::END => {},
+                    code => {
+                        if code == Code::OVERLOAD && self.area == Area::Options {
+                            if let Some(&[overload]) = opt.value().and_then(|v| v.value()) {
+                                self.overload = overload;
+                            }
+                        }
+                        return Some(opt);
+                    },
+                }
+            }
+            if !self.next_area() {
+                self.rest = &[];
+                return None;
+            }
+        }
     }
 }
 
@@ -257,48 +448,185 @@ impl<'a> Header<&'a mut [u8]> {
     }
 }
 
+// Options are staged apart from the header and laid out by `finish`, which
+// keeps the message within its maximum size. Options that do not fit in the
+// options field go to the `file` field and then to the `sname` field, as
+// announced by an OVERLOAD option (RFC 2131, section 4.1), as long as those
+// fields are otherwise unused.
 pub struct Builder {
     buf: Vec<u8>,
+    opts: Vec<u8>,
+    max_size: usize,
+    packet: Vec<u8>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Builder {
+    const FILE_RANGE: (usize, usize) = (108, 108 + 128);
+    const SNAME_RANGE: (usize, usize) = (44, 44 + 64);
+
     pub fn new() -> Self {
         let buf = vec![0u8; Header::<()>::SIZE];
-        let mut bldr = Self { buf };
+        let mut bldr = Self {
+            buf,
+            opts: Vec::new(),
+            max_size: Message::<()>::DEFAULT_MAX_SIZE,
+            packet: Vec::new(),
+        };
         bldr.reset();
         bldr
     }
 
+    pub fn header(&self) -> Header<&[u8]> {
+        Header::<&_>::new(&self.buf[..Header::<()>::SIZE]).unwrap()
+    }
+
     pub fn header_mut(&mut self) -> Header<&mut [u8]> {
         Header::<&mut _>::new(&mut self.buf[..Header::<()>::SIZE]).unwrap()
     }
 
-    pub fn options_builder(&mut self) -> options::Builder {
-        options::Builder(AppendOnly(&mut self.buf))
+    pub fn options_builder(&mut self) -> options::Builder<'_> {
+        options::Builder(AppendOnly(&mut self.opts))
     }
 
-    fn fill_to_min_size(&mut self) {
-        if self.buf.len() < Message::<()>::MIN_SIZE {
-            self.buf.resize(Message::<()>::MIN_SIZE, 0);
-        }
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    // Limits the finished message to `max_size` bytes, typically the
+    // client's MAXIMUM_DHCP_MESSAGE_SIZE less `IP_UDP_HEADER_SIZE`. Limits
+    // below the default are raised to it.
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size.max(Message::<()>::DEFAULT_MAX_SIZE);
     }
 
-    pub fn finish_owned(mut self) -> Vec<u8> {
-        self.fill_to_min_size();
-        self.buf
+    pub fn finish_owned(self) -> Result<Vec<u8>, TooLarge> {
+        self.layout()
     }
 
-    pub fn finish(&mut self) -> &[u8] {
-        self.fill_to_min_size();
-        &self.buf[..]
+    pub fn finish(&mut self) -> Result<&[u8], TooLarge> {
+        self.packet = self.layout()?;
+        Ok(&self.packet[..])
     }
 
     pub fn reset(&mut self) {
         self.buf.truncate(Header::<()>::SIZE);
         self.header_mut().reset_to_default();
+        self.opts.clear();
+    }
+
+    fn layout(&self) -> Result<Vec<u8>, TooLarge> {
+        let too_large = TooLarge { max_size: self.max_size };
+        let mut packet = self.buf.clone();
+        if packet.len() + self.opts.len() <= self.max_size {
+            packet.extend_from_slice(&self.opts);
+        } else {
+            let cookie = &options::Options::<()>::MAGIC_COOKIE[..];
+            if !self.opts.starts_with(cookie) {
+                return Err(too_large);
+            }
+            let items = split_options(&self.opts[cookie.len()..]).ok_or(too_large)?;
+            let Code(overload) = Code::OVERLOAD;
+            let Code(end) = Code::END;
+            // Room for the cookie, the OVERLOAD option and END.
+            let reserved = packet.len() + cookie.len() + 3 + 1;
+            let mut areas = vec![(self.max_size.saturating_sub(reserved), Vec::new())];
+            let mut fields = Vec::new();
+            for &(flag, (start, stop)) in &[(1u8, Self::FILE_RANGE), (2u8, Self::SNAME_RANGE)] {
+                if packet[start..stop].iter().all(|&b| b == 0) {
+                    areas.push((stop - start - 1, Vec::new()));
+                    fields.push((flag, start));
+                }
+            }
+            let mut area = 0;
+            for (code, value) in items {
+                // Values up to 255 bytes stay in one piece; longer ones are
+                // already split and may be split anywhere (RFC 3396).
+                let mut rest = &value[..];
+                let splittable = value.len() > 255;
+                loop {
+                    let (room, bytes) = &mut areas[area];
+                    let room = *room - bytes.len();
+                    let fits = if splittable { room > 2 } else { room >= rest.len() + 2 };
+                    if !fits {
+                        area += 1;
+                        if area == areas.len() {
+                            return Err(too_large);
+                        }
+                        continue;
+                    }
+                    let len = rest.len().min(255).min(room - 2);
+                    bytes.extend_from_slice(&[code, len as u8]);
+                    bytes.extend_from_slice(&rest[..len]);
+                    rest = &rest[len..];
+                    if rest.is_empty() {
+                        break;
+                    }
+                }
+            }
+            let mut flags = 0;
+            for (&(flag, start), (_, bytes)) in fields.iter().zip(&areas[1..]) {
+                if !bytes.is_empty() {
+                    packet[start..start + bytes.len()].copy_from_slice(bytes);
+                    packet[start + bytes.len()] = end;
+                    flags |= flag;
+                }
+            }
+            packet.extend_from_slice(cookie);
+            if flags != 0 {
+                packet.extend_from_slice(&[overload, 1, flags]);
+            }
+            packet.extend_from_slice(&areas[0].1);
+            packet.push(end);
+        }
+        if packet.len() < Message::<()>::MIN_SIZE {
+            packet.resize(Message::<()>::MIN_SIZE, 0);
+        }
+        Ok(packet)
+    }
+}
+
+// Splits encoded options into codes and values, joining consecutive
+// instances of the same code and dropping padding and anything from END on.
+// Fails on a truncated option.
+fn split_options(mut buf: &[u8]) -> Option<Vec<(u8, Vec<u8>)>> {
+    let mut items: Vec<(u8, Vec<u8>)> = Vec::new();
+    while let Some(&code) = buf.first() {
+        match Code(code) {
+            Code::PAD => buf = &buf[1..],
+            Code::END => break,
+            _ => {
+                let len = *buf.get(1)? as usize;
+                let value = buf.get(2..2 + len)?;
+                match items.last_mut() {
+                    Some((last, joined)) if *last == code => joined.extend_from_slice(value),
+                    _ => items.push((code, value.to_vec())),
+                }
+                buf = &buf[2 + len..];
+            },
+        }
+    }
+    Some(items)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TooLarge {
+    pub max_size: usize,
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "message does not fit in {} bytes", self.max_size)
     }
 }
 
+impl StdError for TooLarge {}
+
 pub struct AppendOnly<'a>(&'a mut Vec<u8>);
 impl<'a> AppendOnly<'a> {
     #[inline]
@@ -306,3 +634,110 @@ impl<'a> AppendOnly<'a> {
         self.0.extend(buf);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::options::bytes::AddBytesExt;
+    use crate::options::end::AddEndExt;
+
+    fn builder(opts: &[(u8, usize)]) -> Builder {
+        let mut bldr = Builder::new();
+        {
+            let mut opts_bldr = bldr.options_builder();
+            opts_bldr.add_magic_cookie();
+            for &(code, len) in opts {
+                opts_bldr.add_bytes(Code(code), &value(code, len));
+            }
+            opts_bldr.add_end();
+        }
+        bldr
+    }
+
+    fn value(code: u8, len: usize) -> Vec<u8> {
+        (0..len).map(|i| code.wrapping_add(i as u8)).collect()
+    }
+
+    fn read(packet: &[u8]) -> BTreeMap<Code, Vec<u8>> {
+        let m = Message::parse(packet).unwrap();
+        options::concatenate(m.try_iter_options().unwrap())
+    }
+
+    fn expected(opts: &[(u8, usize)], overload: Option<u8>) -> BTreeMap<Code, Vec<u8>> {
+        let mut map: BTreeMap<_, _> = opts.iter().map(|&(code, len)| (Code(code), value(code, len))).collect();
+        if let Some(overload) = overload {
+            map.insert(Code::OVERLOAD, vec![overload]);
+        }
+        map
+    }
+
+    #[test]
+    fn fits_in_options_field() {
+        let opts = [(224, 10)];
+        let packet = builder(&opts).finish_owned().unwrap();
+        assert_eq!(packet.len(), Message::<()>::MIN_SIZE);
+        assert_eq!(read(&packet), expected(&opts, None));
+    }
+
+    #[test]
+    fn overloads_file_then_sname() {
+        let opts = [(224, 250), (225, 100), (226, 50), (227, 8)];
+        let packet = builder(&opts).finish_owned().unwrap();
+        assert!(packet.len() <= Message::<()>::DEFAULT_MAX_SIZE);
+        assert_eq!(read(&packet), expected(&opts, Some(3)));
+        let m = Message::new(&packet).unwrap();
+        assert_eq!(m.header().file()[..2], [225, 100]);
+        assert_eq!(m.header().sname()[..2], [226, 50]);
+    }
+
+    #[test]
+    fn options_field_holds_312_bytes() {
+        // 319 bytes of options would fit in 576, but not in 548.
+        let opts = [(224, 250), (225, 60)];
+        let packet = builder(&opts).finish_owned().unwrap();
+        assert!(packet.len() <= 548);
+        assert_eq!(read(&packet), expected(&opts, Some(1)));
+        let m = Message::new(&packet).unwrap();
+        assert_eq!(m.header().file()[..2], [225, 60]);
+        assert!(m.header().sname().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn splits_long_values_across_fields() {
+        let opts = [(224, 450)];
+        let packet = builder(&opts).finish_owned().unwrap();
+        assert!(packet.len() <= Message::<()>::DEFAULT_MAX_SIZE);
+        assert_eq!(read(&packet), expected(&opts, Some(3)));
+    }
+
+    #[test]
+    fn leaves_used_fields_alone() {
+        let opts = [(224, 250), (225, 40), (226, 30)];
+        let mut bldr = builder(&opts);
+        bldr.header_mut().file()[..10].copy_from_slice(b"pxelinux.0");
+        let packet = bldr.finish_owned().unwrap();
+        assert_eq!(read(&packet), expected(&opts, Some(2)));
+        let m = Message::new(&packet).unwrap();
+        assert_eq!(&m.header().file()[..11], b"pxelinux.0\0");
+    }
+
+    #[test]
+    fn too_large() {
+        let bldr = builder(&[(224, 1000)]);
+        assert_eq!(bldr.finish_owned(), Err(TooLarge { max_size: Message::<()>::DEFAULT_MAX_SIZE }));
+    }
+
+    #[test]
+    fn larger_max_size_needs_no_overload() {
+        let opts = [(224, 1000)];
+        let mut bldr = builder(&opts);
+        bldr.set_max_size(100);
+        assert_eq!(bldr.max_size(), Message::<()>::DEFAULT_MAX_SIZE);
+        bldr.set_max_size(1500);
+        let packet = bldr.finish_owned().unwrap();
+        assert!(packet.len() > Message::<()>::DEFAULT_MAX_SIZE);
+        assert_eq!(read(&packet), expected(&opts, None));
+    }
+}
@@ -10,7 +10,7 @@ impl<'a> Option<&'a [u8]>
 {
     #[inline]
     pub fn read(buf: &'a [u8]) -> StdOption<(Option<&'a [u8]>, &'a [u8])> {
-        if buf.len() >= 1 {
+        if !buf.is_empty() {
             let opt = Option(buf);
             match opt.code() {
                 Code::PAD | Code::END => {
@@ -18,10 +18,10 @@ impl<'a> Option<&'a [u8]>
                     return Some((Option(bytes), rest));
                 },
                 _ => {
-                    return opt.value().map(|value| {
-                        let (bytes, rest) = buf.split_at(value.len() as usize + 2);
-                        (Option(bytes), rest)
-                    });
+                    // The declared length may run past the end of `buf`.
+                    let len = opt.value()?.value()?.len();
+                    let (bytes, rest) = buf.split_at(len + 2);
+                    return Some((Option(bytes), rest));
                 },
             }
         }
@@ -30,7 +30,7 @@ impl<'a> Option<&'a [u8]>
 
     #[inline]
     pub fn as_slice(&self) -> &'a [u8] {
-        self.0.as_ref()
+        self.0
     }
 
     #[inline]
@@ -50,7 +50,7 @@ pub struct Value<B>(B);
 impl<'a> Value<&'a [u8]> {
     #[inline]
     pub fn new(buf: &'a [u8]) -> StdOption<Value<&'a [u8]>> {
-        if buf.len() >= 1 {
+        if !buf.is_empty() {
             return Some(Value(buf));
         }
         None
@@ -58,7 +58,7 @@ impl<'a> Value<&'a [u8]> {
 
     #[inline]
     pub fn as_slice(&self) -> &'a [u8] {
-        self.0.as_ref()
+        self.0
     }
 
     #[inline]
@@ -66,27 +66,28 @@ impl<'a> Value<&'a [u8]> {
         self.as_slice()[0]
     }
 
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     #[inline]
     pub fn value(&self) -> StdOption<&'a [u8]> {
-        let value_bytes = &self.as_slice()[1..];
-        if value_bytes.len() >= self.len() as usize {
-            return Some(value_bytes);
-        }
-        None
+        self.as_slice()[1..].get(..self.len() as usize)
     }
 }
 
-impl<'a> Into<(Code, StdOption<Value<&'a [u8]>>)> for Option<&'a [u8]> {
-    fn into(self) -> (Code, StdOption<Value<&'a [u8]>>) {
-        let code = self.code();
-        let value = self.value();
+impl<'a> From<Option<&'a [u8]>> for (Code, StdOption<Value<&'a [u8]>>) {
+    fn from(opt: Option<&'a [u8]>) -> Self {
+        let code = opt.code();
+        let value = opt.value();
         (code, value)
     }
 }
 
-impl<'a> Into<StdOption<(Code, Value<&'a [u8]>)>> for Option<&'a [u8]> {
-    fn into(self) -> StdOption<(Code, Value<&'a [u8]>)> {
-        let (k, v) = self.into();
+impl<'a> From<Option<&'a [u8]>> for StdOption<(Code, Value<&'a [u8]>)> {
+    fn from(opt: Option<&'a [u8]>) -> Self {
+        let (k, v) = opt.into();
         v.map(|v| (k, v))
     }
 }
@@ -79,5 +79,6 @@ impl Code {
     pub const DEFAULT_INTERNET_RELAY_CHAT_SERVER               : Code = Code(74);
     pub const STREET_TALK_SERVER                               : Code = Code(75);
     pub const STREET_TALK_DIRECTORY_ASSISTANCE_SERVER          : Code = Code(76);
+    pub const RELAY_AGENT_INFORMATION                          : Code = Code(82);
     pub const END                                              : Code = Code(255);
 }
@@ -14,13 +14,21 @@ pub mod domain_name_servers;
 pub mod bytes;
 pub mod domain_name;
 pub mod host_name;
+pub mod client_identifier;
+pub mod parameter_request_list;
+pub mod maximum_dhcp_message_size;
 pub mod requested_ip_address;
 pub mod lease_time;
 pub mod message_type;
 pub mod server_identifier;
+pub mod relay_agent_information;
 pub mod end;
+pub mod set;
+pub use set::OptionSet;
+pub mod typed;
+pub use typed::DhcpOption;
 
-pub struct Options<B>(B);
+pub struct Options<B>(pub(crate) B);
 
 impl<T> Options<T> {
     pub const MIN_SIZE: usize = 64;
@@ -39,7 +47,7 @@ impl<'a> Options<&'a [u8]> {
 
     #[inline]
     pub fn as_slice(&self) -> &[u8] {
-        self.0.as_ref()
+        self.0
     }
 
     #[inline]
@@ -55,7 +63,7 @@ impl<'a> Options<&'a [u8]> {
     }
 
     #[inline]
-    pub fn try_iter(&self) -> Option<Iter> {
+    pub fn try_iter(&self) -> Option<Iter<'_>> {
         if !self.is_magic_cookie_valid() {
             return None;
         }
@@ -112,21 +120,122 @@ impl<'a> DerefMut for Builder<'a> {
     }
 }
 
+// Looks up the value of an option. The value of an option that appears more
+// than once is the concatenation of all its instances (RFC 3396), which maps
+// built with `concatenate` provide.
 pub trait OptionMap {
-    fn get_option(&self, code: Code) -> Option<&option::Value<&[u8]>>;
+    fn get_option(&self, code: Code) -> Option<&[u8]>;
 }
 
-impl<'a, S> OptionMap for HashMap<Code, option::Value<&'a [u8]>, S>
+impl<S> OptionMap for HashMap<Code, option::Value<&[u8]>, S>
 where
     S: BuildHasher,
 {
-    fn get_option(&self, code: Code) -> Option<&option::Value<&[u8]>> {
-        self.get(&code)
+    fn get_option(&self, code: Code) -> Option<&[u8]> {
+        self.get(&code)?.value()
     }
 }
 
-impl<'a> OptionMap for BTreeMap<Code, option::Value<&'a [u8]>> {
-    fn get_option(&self, code: Code) -> Option<&option::Value<&[u8]>> {
-        self.get(&code)
+impl OptionMap for BTreeMap<Code, option::Value<&[u8]>> {
+    fn get_option(&self, code: Code) -> Option<&[u8]> {
+        self.get(&code)?.value()
+    }
+}
+
+impl<S> OptionMap for HashMap<Code, Vec<u8>, S>
+where
+    S: BuildHasher,
+{
+    fn get_option(&self, code: Code) -> Option<&[u8]> {
+        self.get(&code).map(|value| &value[..])
+    }
+}
+
+impl OptionMap for BTreeMap<Code, Vec<u8>> {
+    fn get_option(&self, code: Code) -> Option<&[u8]> {
+        self.get(&code).map(|value| &value[..])
+    }
+}
+
+// Collects options into a map, joining the values of options that appear
+// more than once in the order they appear (RFC 3396). Padding, END and
+// options with a truncated value are skipped.
+pub fn concatenate<'a, I>(iter: I) -> BTreeMap<Code, Vec<u8>>
+where
+    I: IntoIterator<Item = option::Option<&'a [u8]>>,
+{
+    let mut map: BTreeMap<Code, Vec<u8>> = BTreeMap::new();
+    for opt in iter {
+        match opt.code() {
+            Code::PAD | Code::END => {},
+            code => {
+                if let Some(bytes) = opt.value().and_then(|value| value.value()) {
+                    map.entry(code).or_default().extend_from_slice(bytes);
+                }
+            },
+        }
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::bytes::{AddBytesExt, GetBytesExt};
+    use crate::message;
+
+    fn options(body: &[u8]) -> Vec<u8> {
+        let mut buf = Options::<()>::MAGIC_COOKIE.to_vec();
+        buf.extend_from_slice(body);
+        buf
+    }
+
+    fn concatenated(body: &[u8]) -> BTreeMap<Code, Vec<u8>> {
+        let buf = options(body);
+        concatenate(Options(&buf[..]).try_iter().unwrap())
+    }
+
+    #[test]
+    fn joins_instances_in_order() {
+        let map = concatenated(&[224, 2, 1, 2, 225, 1, 9, 224, 3, 3, 4, 5, 224, 1, 6, 255]);
+        assert_eq!(map.get_bytes(Code(224)), Some(&[1, 2, 3, 4, 5, 6][..]));
+        assert_eq!(map.get_bytes(Code(225)), Some(&[9][..]));
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn skips_padding_and_end() {
+        let map = concatenated(&[0, 0, 224, 1, 7, 0, 255, 0]);
+        assert_eq!(map.keys().collect::<Vec<_>>(), [&Code(224)]);
+    }
+
+    #[test]
+    fn keeps_empty_instances() {
+        let map = concatenated(&[224, 0, 225, 0, 225, 2, 1, 2, 255]);
+        assert_eq!(map.get_bytes(Code(224)), Some(&[][..]));
+        assert_eq!(map.get_bytes(Code(225)), Some(&[1, 2][..]));
+    }
+
+    #[test]
+    fn stops_at_truncated_option() {
+        let map = concatenated(&[224, 1, 1, 225, 5, 1, 2]);
+        assert_eq!(map.get_bytes(Code(224)), Some(&[1][..]));
+        assert_eq!(map.get_bytes(Code(225)), None);
+    }
+
+    #[test]
+    fn round_trips_split_values() {
+        let value: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let mut bldr = message::Builder::new();
+        bldr.set_max_size(1500);
+        {
+            let mut opts = bldr.options_builder();
+            opts.add_magic_cookie();
+            opts.add_bytes(Code(224), &value);
+        }
+        let packet = bldr.finish_owned().unwrap();
+        let m = message::Message::new(&packet).unwrap();
+        let map = concatenate(m.try_iter_options().unwrap());
+        assert_eq!(map.get_bytes(Code(224)), Some(&value[..]));
     }
 }
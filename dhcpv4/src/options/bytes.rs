@@ -5,18 +5,24 @@ pub trait AddBytesExt {
     fn add_bytes(&mut self, code: Code, bytes: &[u8]);
 }
 
+// Values longer than 255 bytes are split into consecutive options with the
+// same code (RFC 3396).
 impl<'a> AddBytesExt for Builder<'a> {
     fn add_bytes(&mut self, code: Code, bytes: &[u8]) {
         let Code(code) = code;
-        self.append(&[code, bytes.len() as u8]);
-        self.append(bytes);
+        if bytes.is_empty() {
+            self.append(&[code, 0]);
+        }
+        for chunk in bytes.chunks(255) {
+            self.append(&[code, chunk.len() as u8]);
+            self.append(chunk);
+        }
     }
 }
 
 pub trait GetBytesExt: OptionMap {
     fn get_bytes(&self, code: Code) -> Option<&[u8]> {
-        let value = self.get_option(code)?;
-        value.value()
+        self.get_option(code)
     }
 }
 
@@ -0,0 +1,20 @@
+use super::super::option::Code;
+use super::bytes::{AddBytesExt, GetBytesExt};
+
+pub trait AddClientIdentifierExt: AddBytesExt {
+    fn add_client_identifier(&mut self, client_identifier: &[u8]) {
+        self.add_bytes(Code::CLIENT_IDENTIFIER, client_identifier);
+    }
+}
+
+impl<T: AddBytesExt> AddClientIdentifierExt for T {}
+
+pub trait GetClientIdentifierExt: GetBytesExt {
+    fn get_client_identifier(&self) -> Option<&[u8]>;
+}
+
+impl<T: GetBytesExt> GetClientIdentifierExt for T {
+    fn get_client_identifier(&self) -> Option<&[u8]> {
+        self.get_bytes(Code::CLIENT_IDENTIFIER)
+    }
+}
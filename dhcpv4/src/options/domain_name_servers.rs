@@ -17,7 +17,7 @@ pub trait AddDomainNameServersExt: AddIpsExt {
 impl<T: AddIpsExt> AddDomainNameServersExt for T {}
 
 pub trait GetDomainNameExt: GetIpsExt {
-    fn get_domain_name_servers(&self) -> Option<IpsIter> {
+    fn get_domain_name_servers(&self) -> Option<IpsIter<'_>> {
         self.get_ips(Code::DOMAIN_NAME_SERVER)
     }
 }
@@ -2,7 +2,8 @@ use std::net::Ipv4Addr;
 use std::convert::TryInto;
 use std::borrow::Borrow;
 use super::super::option::Code;
-use super::{Builder, OptionMap};
+use super::OptionMap;
+use super::bytes::AddBytesExt;
 
 pub trait AddIpsExt {
     fn add_ips<I, A>(&mut self, code: Code, ips: I)
@@ -13,26 +14,25 @@ pub trait AddIpsExt {
     ;
 }
 
-impl<'a> AddIpsExt for Builder<'a> {
+impl<T: AddBytesExt> AddIpsExt for T {
     fn add_ips<I, A>(&mut self, code: Code, ips: I)
     where
         A: Borrow<Ipv4Addr>,
         I: IntoIterator<Item = A>,
         I::IntoIter: ExactSizeIterator,
     {
-        let Code(code) = code;
         let iter = ips.into_iter();
-        self.append(&[code, (iter.len() * 4) as u8]);
+        let mut bytes = Vec::with_capacity(iter.len() * 4);
         for ip in iter {
-            self.append(&ip.borrow().octets());
+            bytes.extend_from_slice(&ip.borrow().octets());
         }
+        self.add_bytes(code, &bytes);
     }
 }
 
 pub trait GetIpsExt: OptionMap {
-    fn get_ips(&self, code: Code) -> Option<IpsIter> {
-        let value = self.get_option(code)?;
-        let bytes = value.value()?;
+    fn get_ips(&self, code: Code) -> Option<IpsIter<'_>> {
+        let bytes = self.get_option(code)?;
         if bytes.len() % 4 == 0 {
             return Some(IpsIter(bytes));
         }
@@ -47,7 +47,7 @@ impl<'a> Iterator for IpsIter<'a> {
     type Item = Ipv4Addr;
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        let octets: [u8; 4] = self.0.get(..4)?.try_into().unwrap();
+        let octets: [u8; 4] = self.0.get(..4)?.try_into().ok()?;
         self.0 = &self.0[4..];
         Some(Ipv4Addr::from(octets))
     }
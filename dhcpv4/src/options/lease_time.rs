@@ -1,23 +1,21 @@
 use std::convert::TryInto;
 use super::super::option::Code;
-use super::{Builder, OptionMap};
+use super::OptionMap;
+use super::bytes::AddBytesExt;
 
 pub trait AddLeaseTimeExt {
     fn add_lease_time(&mut self, time_in_secs: u32);
 }
 
-impl<'a> AddLeaseTimeExt for Builder<'a> {
+impl<T: AddBytesExt> AddLeaseTimeExt for T {
     fn add_lease_time(&mut self, time_in_secs: u32) {
-        let Code(code) = Code::IP_ADDRESS_LEASE_TIME;
-        self.append(&[code, 4]);
-        self.append(&time_in_secs.to_be_bytes());
+        self.add_bytes(Code::IP_ADDRESS_LEASE_TIME, &time_in_secs.to_be_bytes());
     }
 }
 
 pub trait GetLeaseTimeExt: OptionMap {
     fn get_lease_time(&mut self) -> Option<u32> {
-        let value = self.get_option(Code::IP_ADDRESS_LEASE_TIME)?;
-        let bytes = value.value()?;
+        let bytes = self.get_option(Code::IP_ADDRESS_LEASE_TIME)?;
         Some(u32::from_be_bytes(bytes.try_into().ok()?))
     }
 }
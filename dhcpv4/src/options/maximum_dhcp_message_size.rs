@@ -0,0 +1,23 @@
+use std::convert::TryInto;
+use super::super::option::Code;
+use super::OptionMap;
+use super::bytes::AddBytesExt;
+
+pub trait AddMaximumDhcpMessageSizeExt {
+    fn add_maximum_dhcp_message_size(&mut self, size: u16);
+}
+
+impl<T: AddBytesExt> AddMaximumDhcpMessageSizeExt for T {
+    fn add_maximum_dhcp_message_size(&mut self, size: u16) {
+        self.add_bytes(Code::MAXIMUM_DHCP_MESSAGE_SIZE, &size.to_be_bytes());
+    }
+}
+
+pub trait GetMaximumDhcpMessageSizeExt: OptionMap {
+    fn get_maximum_dhcp_message_size(&self) -> Option<u16> {
+        let bytes = self.get_option(Code::MAXIMUM_DHCP_MESSAGE_SIZE)?;
+        Some(u16::from_be_bytes(bytes.try_into().ok()?))
+    }
+}
+
+impl<T: OptionMap> GetMaximumDhcpMessageSizeExt for T {}
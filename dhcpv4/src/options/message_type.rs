@@ -1,6 +1,6 @@
 use super::super::option::Code;
 use super::OptionMap;
-use super::Builder;
+use super::bytes::AddBytesExt;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct MessageType(pub u8);
@@ -19,18 +19,16 @@ pub trait AddMessageTypeExt {
     fn add_message_type(&mut self, typ: MessageType);
 }
 
-impl<'a> AddMessageTypeExt for Builder<'a> {
+impl<T: AddBytesExt> AddMessageTypeExt for T {
     fn add_message_type(&mut self, typ: MessageType) {
         let MessageType(typ) = typ;
-        let Code(code) = Code::DHCP_MESSAGE_TYPE;
-        self.append(&[code, 1, typ]);
+        self.add_bytes(Code::DHCP_MESSAGE_TYPE, &[typ]);
     }
 }
 
 pub trait GetMessageTypeExt: OptionMap {
     fn get_message_type(&self) -> Option<MessageType> {
-        let value = self.get_option(Code::DHCP_MESSAGE_TYPE)?;
-        let bytes = value.value()?;
+        let bytes = self.get_option(Code::DHCP_MESSAGE_TYPE)?;
         if bytes.len() == 1 {
             return Some(MessageType(bytes[0]));
         }
@@ -0,0 +1,40 @@
+use super::super::option::Code;
+use super::bytes::{AddBytesExt, GetBytesExt};
+
+pub trait AddParameterRequestListExt: AddBytesExt {
+    fn add_parameter_request_list<I>(&mut self, codes: I)
+    where
+        I: IntoIterator<Item = Code>,
+    {
+        let bytes: Vec<u8> = codes.into_iter().map(|Code(code)| code).collect();
+        self.add_bytes(Code::PARAMETER_REQUEST_LIST, &bytes);
+    }
+}
+
+impl<T: AddBytesExt> AddParameterRequestListExt for T {}
+
+pub trait GetParameterRequestListExt: GetBytesExt {
+    fn get_parameter_request_list(&self) -> Option<CodesIter<'_>> {
+        self.get_bytes(Code::PARAMETER_REQUEST_LIST).map(CodesIter)
+    }
+}
+
+impl<T: GetBytesExt> GetParameterRequestListExt for T {}
+
+// The requested codes, in the client's order of preference.
+pub struct CodesIter<'a>(&'a [u8]);
+impl<'a> Iterator for CodesIter<'a> {
+    type Item = Code;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&code, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(Code(code))
+    }
+}
+
+impl<'a> ExactSizeIterator for CodesIter<'a> {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
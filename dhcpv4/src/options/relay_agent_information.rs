@@ -0,0 +1,149 @@
+use super::super::option::Code;
+use super::OptionMap;
+use super::bytes::AddBytesExt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SubCode(pub u8);
+impl SubCode {
+    pub const AGENT_CIRCUIT_ID: SubCode = SubCode(1);
+    pub const AGENT_REMOTE_ID: SubCode = SubCode(2);
+}
+
+// The sub-options a relay agent attaches to the requests it forwards
+// (RFC 3046), in the order they appear. Servers echo the option back
+// unchanged, and the agent strips it before passing the reply on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RelayAgentInformation(Vec<(SubCode, Vec<u8>)>);
+
+impl RelayAgentInformation {
+    pub const MAX_SUB_OPTION_LEN: usize = 255;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // None unless `bytes` is one or more complete sub-options.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let mut info = Self::new();
+        let mut rest = bytes;
+        while let Some((&code, tail)) = rest.split_first() {
+            let (&len, tail) = tail.split_first()?;
+            let value = tail.get(..usize::from(len))?;
+            info.0.push((SubCode(code), value.to_vec()));
+            rest = &tail[value.len()..];
+        }
+        if info.0.is_empty() {
+            return None;
+        }
+        Some(info)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // The value of the first sub-option with `code`.
+    pub fn get(&self, code: SubCode) -> Option<&[u8]> {
+        self.0.iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, value)| &value[..])
+    }
+
+    pub fn circuit_id(&self) -> Option<&[u8]> {
+        self.get(SubCode::AGENT_CIRCUIT_ID)
+    }
+
+    pub fn remote_id(&self) -> Option<&[u8]> {
+        self.get(SubCode::AGENT_REMOTE_ID)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SubCode, &[u8])> {
+        self.0.iter().map(|(code, value)| (*code, &value[..]))
+    }
+
+    // Appends a sub-option. Unlike options, sub-options cannot be split, so
+    // None if `value` does not fit in one.
+    pub fn push(&mut self, code: SubCode, value: &[u8]) -> Option<&mut Self> {
+        if value.len() > Self::MAX_SUB_OPTION_LEN {
+            return None;
+        }
+        self.0.push((code, value.to_vec()));
+        Some(self)
+    }
+
+    // The sub-options as they go on the wire, the value of the option.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (SubCode(code), value) in &self.0 {
+            bytes.push(*code);
+            bytes.push(value.len() as u8);
+            bytes.extend_from_slice(value);
+        }
+        bytes
+    }
+}
+
+pub trait AddRelayAgentInformationExt: AddBytesExt {
+    fn add_relay_agent_information(&mut self, info: &RelayAgentInformation) {
+        self.add_bytes(Code::RELAY_AGENT_INFORMATION, &info.encode());
+    }
+}
+
+impl<T: AddBytesExt> AddRelayAgentInformationExt for T {}
+
+pub trait GetRelayAgentInformationExt: OptionMap {
+    fn get_relay_agent_information(&self) -> Option<RelayAgentInformation> {
+        RelayAgentInformation::parse(self.get_option(Code::RELAY_AGENT_INFORMATION)?)
+    }
+}
+
+impl<T: OptionMap> GetRelayAgentInformationExt for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let mut info = RelayAgentInformation::new();
+        info.push(SubCode::AGENT_CIRCUIT_ID, &[0, 4, 0, 1]).unwrap()
+            .push(SubCode(9), &[]).unwrap()
+            .push(SubCode::AGENT_REMOTE_ID, &[0xaa; 255]).unwrap();
+        let bytes = info.encode();
+        assert_eq!(bytes[..8], [1, 4, 0, 4, 0, 1, 9, 0]);
+        assert_eq!(bytes.len(), 8 + 2 + 255);
+        let parsed = RelayAgentInformation::parse(&bytes).unwrap();
+        assert_eq!(parsed, info);
+        assert_eq!(parsed.circuit_id(), Some(&[0, 4, 0, 1][..]));
+        assert_eq!(parsed.remote_id(), Some(&[0xaa; 255][..]));
+        assert_eq!(parsed.get(SubCode(9)), Some(&[][..]));
+    }
+
+    #[test]
+    fn keeps_order_and_repeats() {
+        let bytes = [2, 1, 0xbb, 1, 1, 0x01, 2, 1, 0xcc];
+        let info = RelayAgentInformation::parse(&bytes).unwrap();
+        assert_eq!(info.iter().map(|(code, _)| code.0).collect::<Vec<_>>(), [2, 1, 2]);
+        // The first of a repeated sub-option wins.
+        assert_eq!(info.remote_id(), Some(&[0xbb][..]));
+        assert_eq!(info.encode(), bytes);
+    }
+
+    #[test]
+    fn rejects_truncated_sub_options() {
+        assert_eq!(RelayAgentInformation::parse(&[]), None);
+        // No length.
+        assert_eq!(RelayAgentInformation::parse(&[1]), None);
+        // Shorter than its length says.
+        assert_eq!(RelayAgentInformation::parse(&[1, 3, 0, 0]), None);
+        assert_eq!(RelayAgentInformation::parse(&[1, 1, 0, 2, 5, 0]), None);
+    }
+
+    #[test]
+    fn refuses_oversized_sub_options() {
+        let mut info = RelayAgentInformation::new();
+        assert!(info.push(SubCode::AGENT_CIRCUIT_ID, &[0; 256]).is_none());
+        assert!(info.is_empty());
+        assert!(info.push(SubCode::AGENT_CIRCUIT_ID, &[0; 255]).is_some());
+    }
+}
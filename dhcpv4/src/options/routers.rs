@@ -17,7 +17,7 @@ pub trait AddRoutersExt: AddIpsExt {
 impl<T: AddIpsExt> AddRoutersExt for T {}
 
 pub trait GetRoutersExt: GetIpsExt {
-    fn get_routers(&self) -> Option<IpsIter> {
+    fn get_routers(&self) -> Option<IpsIter<'_>> {
         self.get_ips(Code::ROUTER)
     }
 }
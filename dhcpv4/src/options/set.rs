@@ -0,0 +1,301 @@
+use super::super::message::Message;
+use super::super::option::{self, Code};
+use super::bytes::AddBytesExt;
+use super::{Builder, DhcpOption, OptionMap};
+
+// An owned, ordered collection of options. An option may appear more than
+// once: each instance is kept as it came, and `get` returns the
+// concatenation of all of them, which is the value of the option (RFC
+// 3396). `add_bytes`, and with it every `Add*Ext` helper, replaces every
+// instance with a single one instead, keeping the option in place.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OptionSet {
+    entries: Vec<(Code, Vec<u8>)>,
+    // The concatenated values of options that appear more than once.
+    joined: Vec<(Code, Vec<u8>)>,
+}
+
+impl OptionSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Every option of `message`, including those in overloaded `file` and
+    // `sname` fields. None if the magic cookie is wrong.
+    pub fn from_message(message: &Message<&[u8]>) -> Option<Self> {
+        let mut set = Self::new();
+        set.extend(message.try_iter_options()?);
+        Some(set)
+    }
+
+    // The number of instances, counting repeated options once per
+    // instance.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, code: Code) -> bool {
+        self.entries.iter().any(|(c, _)| *c == code)
+    }
+
+    pub fn get(&self, code: Code) -> Option<&[u8]> {
+        self.joined.iter()
+            .chain(&self.entries)
+            .find(|(c, _)| *c == code)
+            .map(|(_, value)| &value[..])
+    }
+
+    // Every instance of `code`, in order.
+    pub fn get_all(&self, code: Code) -> impl Iterator<Item = &[u8]> {
+        self.entries.iter()
+            .filter(move |(c, _)| *c == code)
+            .map(|(_, value)| &value[..])
+    }
+
+    // Every instance of every option, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (Code, &[u8])> {
+        self.entries.iter().map(|(code, value)| (*code, &value[..]))
+    }
+
+    // Replaces every instance of `code` with `value`, where the first one
+    // was, and returns the old value.
+    pub fn insert(&mut self, code: Code, value: Vec<u8>) -> Option<Vec<u8>> {
+        let rest = self.remove_after_first(code);
+        match self.entries.iter_mut().find(|(c, _)| *c == code) {
+            Some((_, first)) => {
+                let mut first = std::mem::replace(first, value);
+                first.extend(rest);
+                Some(first)
+            },
+            None => {
+                self.entries.push((code, value));
+                None
+            },
+        }
+    }
+
+    // Adds another instance of `code` after every option present.
+    pub fn push(&mut self, code: Code, value: Vec<u8>) {
+        self.entries.push((code, value));
+        self.rejoin(code);
+    }
+
+    pub fn remove(&mut self, code: Code) -> Option<Vec<u8>> {
+        let rest = self.remove_after_first(code);
+        let index = self.entries.iter().position(|(c, _)| *c == code)?;
+        let mut value = self.entries.remove(index).1;
+        value.extend(rest);
+        Some(value)
+    }
+
+    pub fn retain<F: FnMut(Code, &[u8]) -> bool>(&mut self, mut f: F) {
+        self.entries.retain(|(code, value)| f(*code, value));
+        self.rejoin_all();
+    }
+
+    // Reorders the options by `f`; options with equal keys, and so every
+    // instance of an option, keep their relative order.
+    pub fn sort_by_key<K: Ord, F: FnMut(Code) -> K>(&mut self, mut f: F) {
+        self.entries.sort_by_key(|(code, _)| f(*code));
+    }
+
+    // Adds every instance of the options of `defaults` that are not set
+    // here, after the options already present.
+    pub fn merge(&mut self, defaults: &OptionSet) {
+        let missing: Vec<_> = defaults.iter()
+            .filter(|&(code, _)| !self.contains(code))
+            .map(|(code, value)| (code, value.to_vec()))
+            .collect();
+        self.entries.extend(missing);
+        self.rejoin_all();
+    }
+
+    // Drops the options whose value has a length their code cannot have,
+    // which a lenient parse lets through, and returns how many there were.
+    pub fn remove_malformed(&mut self) -> usize {
+        let mut malformed: Vec<_> = self.iter()
+            .map(|(code, _)| code)
+            .filter(|&code| !self.get(code).is_some_and(|value| DhcpOption::valid_len(code, value.len())))
+            .collect();
+        malformed.sort();
+        malformed.dedup();
+        self.retain(|code, _| !malformed.contains(&code));
+        malformed.len()
+    }
+
+    // Serializes the options in order, each instance on its own. The
+    // caller adds the magic cookie and END.
+    pub fn write(&self, builder: &mut Builder<'_>) {
+        for (code, value) in self.iter() {
+            builder.add_bytes(code, value);
+        }
+    }
+
+    // Removes every instance of `code` but the first and returns their
+    // concatenated values.
+    fn remove_after_first(&mut self, code: Code) -> Vec<u8> {
+        let mut rest = Vec::new();
+        let mut first = true;
+        self.entries.retain(|(c, value)| {
+            if *c != code {
+                return true;
+            }
+            if !std::mem::replace(&mut first, false) {
+                rest.extend_from_slice(value);
+                return false;
+            }
+            true
+        });
+        self.joined.retain(|(c, _)| *c != code);
+        rest
+    }
+
+    fn rejoin(&mut self, code: Code) {
+        self.joined.retain(|(c, _)| *c != code);
+        if self.get_all(code).nth(1).is_some() {
+            let value = self.get_all(code).flatten().copied().collect();
+            self.joined.push((code, value));
+        }
+    }
+
+    fn rejoin_all(&mut self) {
+        self.joined.clear();
+        let mut codes: Vec<_> = self.entries.iter().map(|&(code, _)| code).collect();
+        codes.sort();
+        codes.dedup();
+        for code in codes {
+            self.rejoin(code);
+        }
+    }
+}
+
+impl AddBytesExt for OptionSet {
+    fn add_bytes(&mut self, code: Code, bytes: &[u8]) {
+        self.insert(code, bytes.to_vec());
+    }
+}
+
+impl OptionMap for OptionSet {
+    fn get_option(&self, code: Code) -> Option<&[u8]> {
+        self.get(code)
+    }
+}
+
+// Padding, END and options with a truncated value are skipped.
+impl<'a> Extend<option::Option<&'a [u8]>> for OptionSet {
+    fn extend<T>(&mut self, iter: T)
+    where
+        T: IntoIterator<Item = option::Option<&'a [u8]>>,
+    {
+        for opt in iter {
+            match opt.code() {
+                Code::PAD | Code::END => {},
+                code => {
+                    if let Some(bytes) = opt.value().and_then(|value| value.value()) {
+                        self.push(code, bytes.to_vec());
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<'a> std::iter::FromIterator<option::Option<&'a [u8]>> for OptionSet {
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = option::Option<&'a [u8]>>,
+    {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message;
+
+    const A: Code = Code(224);
+    const B: Code = Code(225);
+
+    fn set(entries: &[(Code, &[u8])]) -> OptionSet {
+        let mut set = OptionSet::new();
+        for &(code, value) in entries {
+            set.push(code, value.to_vec());
+        }
+        set
+    }
+
+    fn instances(set: &OptionSet) -> Vec<(Code, Vec<u8>)> {
+        set.iter().map(|(code, value)| (code, value.to_vec())).collect()
+    }
+
+    #[test]
+    fn keeps_repeated_instances() {
+        let set = set(&[(A, &[1, 2]), (B, &[9]), (A, &[3])]);
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.get_all(A).collect::<Vec<_>>(), [&[1, 2][..], &[3][..]]);
+        assert_eq!(set.get(A), Some(&[1, 2, 3][..]));
+        assert_eq!(set.get(B), Some(&[9][..]));
+        assert_eq!(set.get(Code(226)), None);
+        assert_eq!(instances(&set), [(A, vec![1, 2]), (B, vec![9]), (A, vec![3])]);
+    }
+
+    #[test]
+    fn insert_replaces_every_instance() {
+        let mut set = set(&[(B, &[9]), (A, &[1]), (B, &[8]), (A, &[2])]);
+        assert_eq!(set.insert(A, vec![7]), Some(vec![1, 2]));
+        assert_eq!(instances(&set), [(B, vec![9]), (A, vec![7]), (B, vec![8])]);
+        assert_eq!(set.get(A), Some(&[7][..]));
+        set.add_bytes(B, &[5]);
+        assert_eq!(instances(&set), [(B, vec![5]), (A, vec![7])]);
+        assert_eq!(set.insert(Code(226), vec![]), None);
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn remove_and_retain() {
+        let mut set = set(&[(A, &[1]), (B, &[9]), (A, &[2]), (B, &[8])]);
+        assert_eq!(set.remove(A), Some(vec![1, 2]));
+        assert_eq!(set.remove(A), None);
+        assert_eq!(set.get(B), Some(&[9, 8][..]));
+        set.push(A, vec![3]);
+        set.retain(|code, value| code == A || value != [9]);
+        assert_eq!(instances(&set), [(B, vec![8]), (A, vec![3])]);
+        assert_eq!(set.get(B), Some(&[8][..]));
+    }
+
+    #[test]
+    fn merge_adds_missing_options() {
+        let mut set = set(&[(A, &[1])]);
+        set.merge(&self::set(&[(A, &[5]), (B, &[2]), (B, &[3])]));
+        assert_eq!(instances(&set), [(A, vec![1]), (B, vec![2]), (B, vec![3])]);
+        assert_eq!(set.get(B), Some(&[2, 3][..]));
+    }
+
+    #[test]
+    fn write_round_trips_through_message() {
+        let long: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let mut set = set(&[(A, &[1]), (B, &[9]), (A, &[2])]);
+        set.insert(Code(226), long.clone());
+        let mut bldr = message::Builder::new();
+        bldr.set_max_size(1500);
+        {
+            let mut opts = bldr.options_builder();
+            opts.add_magic_cookie();
+            set.write(&mut opts);
+        }
+        let packet = bldr.finish_owned().unwrap();
+        let read = OptionSet::from_message(&Message::new(&packet).unwrap()).unwrap();
+        assert_eq!(read.get(A), Some(&[1, 2][..]));
+        assert_eq!(read.get(B), Some(&[9][..]));
+        assert_eq!(read.get(Code(226)), Some(&long[..]));
+        // The long value comes back as the instances it was split into.
+        assert_eq!(read.get_all(Code(226)).map(<[u8]>::len).collect::<Vec<_>>(), [255, 45]);
+    }
+}
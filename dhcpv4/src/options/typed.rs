@@ -0,0 +1,650 @@
+use std::convert::TryInto;
+use std::net::Ipv4Addr;
+
+use super::super::option::Code;
+use super::bytes::AddBytesExt;
+use super::message_type::MessageType;
+use super::relay_agent_information::RelayAgentInformation;
+
+// The options of RFC 2132, and relay agent information (RFC 3046), with
+// their values decoded. Anything else is kept as `Unknown`. Strings must be
+// valid UTF-8.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DhcpOption {
+    SubnetMask(Ipv4Addr),
+    TimeOffset(i32),
+    Routers(Vec<Ipv4Addr>),
+    TimeServers(Vec<Ipv4Addr>),
+    NameServers(Vec<Ipv4Addr>),
+    DomainNameServers(Vec<Ipv4Addr>),
+    LogServers(Vec<Ipv4Addr>),
+    CookieServers(Vec<Ipv4Addr>),
+    LprServers(Vec<Ipv4Addr>),
+    ImpressServers(Vec<Ipv4Addr>),
+    ResourceLocationServers(Vec<Ipv4Addr>),
+    HostName(String),
+    BootFileSize(u16),
+    MeritDumpFile(String),
+    DomainName(String),
+    SwapServer(Ipv4Addr),
+    RootPath(String),
+    ExtensionsPath(String),
+    IpForwarding(bool),
+    NonLocalSourceRouting(bool),
+    // (destination, mask) pairs.
+    PolicyFilter(Vec<(Ipv4Addr, Ipv4Addr)>),
+    MaximumDatagramReassemblySize(u16),
+    DefaultIpTtl(u8),
+    PathMtuAgingTimeout(u32),
+    PathMtuPlateauTable(Vec<u16>),
+    InterfaceMtu(u16),
+    AllSubnetsAreLocal(bool),
+    BroadcastAddress(Ipv4Addr),
+    PerformMaskDiscovery(bool),
+    MaskSupplier(bool),
+    PerformRouterDiscovery(bool),
+    RouterSolicitationAddress(Ipv4Addr),
+    // (destination, router) pairs.
+    StaticRoutes(Vec<(Ipv4Addr, Ipv4Addr)>),
+    TrailerEncapsulation(bool),
+    ArpCacheTimeout(u32),
+    EthernetEncapsulation(bool),
+    DefaultTcpTtl(u8),
+    TcpKeepaliveInterval(u32),
+    TcpKeepaliveGarbage(bool),
+    NisDomain(String),
+    NisServers(Vec<Ipv4Addr>),
+    NtpServers(Vec<Ipv4Addr>),
+    VendorSpecificInformation(Vec<u8>),
+    NetbiosNameServers(Vec<Ipv4Addr>),
+    NetbiosDatagramDistributionServers(Vec<Ipv4Addr>),
+    // 1 = B-node, 2 = P-node, 4 = M-node, 8 = H-node.
+    NetbiosNodeType(u8),
+    NetbiosScope(String),
+    XWindowFontServers(Vec<Ipv4Addr>),
+    XWindowDisplayManagers(Vec<Ipv4Addr>),
+    RequestedIpAddress(Ipv4Addr),
+    LeaseTime(u32),
+    Overload(u8),
+    MessageType(MessageType),
+    ServerIdentifier(Ipv4Addr),
+    ParameterRequestList(Vec<Code>),
+    Message(String),
+    MaximumDhcpMessageSize(u16),
+    RenewalTime(u32),
+    RebindingTime(u32),
+    ClassIdentifier(Vec<u8>),
+    ClientIdentifier(Vec<u8>),
+    NisPlusDomain(String),
+    NisPlusServers(Vec<Ipv4Addr>),
+    TftpServerName(String),
+    BootfileName(String),
+    MobileIpHomeAgents(Vec<Ipv4Addr>),
+    SmtpServers(Vec<Ipv4Addr>),
+    Pop3Servers(Vec<Ipv4Addr>),
+    NntpServers(Vec<Ipv4Addr>),
+    WwwServers(Vec<Ipv4Addr>),
+    FingerServers(Vec<Ipv4Addr>),
+    IrcServers(Vec<Ipv4Addr>),
+    StreetTalkServers(Vec<Ipv4Addr>),
+    StdaServers(Vec<Ipv4Addr>),
+    RelayAgentInformation(RelayAgentInformation),
+    Unknown(Code, Vec<u8>),
+}
+
+impl DhcpOption {
+    // Decodes the value of option `code`. None if the value does not have
+    // the shape RFC 2132 gives the option.
+    pub fn parse(code: Code, bytes: &[u8]) -> Option<Self> {
+        use DhcpOption::*;
+        let opt = match code {
+            Code::SUBNET_MASK => SubnetMask(ip(bytes)?),
+            Code::TIME_OFFSET => TimeOffset(i32::from_be_bytes(bytes.try_into().ok()?)),
+            Code::ROUTER => Routers(ips(bytes, 4)?),
+            Code::TIME_SERVER => TimeServers(ips(bytes, 4)?),
+            Code::NAME_SERVER => NameServers(ips(bytes, 4)?),
+            Code::DOMAIN_NAME_SERVER => DomainNameServers(ips(bytes, 4)?),
+            Code::LOG_SERVER => LogServers(ips(bytes, 4)?),
+            Code::QUOTE_SERVER => CookieServers(ips(bytes, 4)?),
+            Code::LPR_SERVER => LprServers(ips(bytes, 4)?),
+            Code::IMPRESS_SERVER => ImpressServers(ips(bytes, 4)?),
+            Code::RESOURCE_LOCATION_SERVER => ResourceLocationServers(ips(bytes, 4)?),
+            Code::HOST_NAME => HostName(string(bytes)?),
+            Code::BOOT_FILE_SIZE => BootFileSize(u16::from_be_bytes(bytes.try_into().ok()?)),
+            Code::MERIT_DUMP_FILE => MeritDumpFile(string(bytes)?),
+            Code::DOMAIN_NAME => DomainName(string(bytes)?),
+            Code::SWAP_SERVER => SwapServer(ip(bytes)?),
+            Code::ROOT_PATH => RootPath(string(bytes)?),
+            Code::EXTENSIONS_PATH => ExtensionsPath(string(bytes)?),
+            Code::IP_FORWARDING => IpForwarding(flag(bytes)?),
+            Code::NON_LOCAL_SOURCE_ROUTING => NonLocalSourceRouting(flag(bytes)?),
+            Code::POLICY_FILTER => PolicyFilter(ip_pairs(bytes)?),
+            Code::MAXIMUM_DATAGRAM_ASSEMBLY_SIZE => MaximumDatagramReassemblySize(u16::from_be_bytes(bytes.try_into().ok()?)),
+            Code::DEFAULT_IPTTL => DefaultIpTtl(byte(bytes)?),
+            Code::PATH_MTU_AGING_TIMEOUT => PathMtuAgingTimeout(u32::from_be_bytes(bytes.try_into().ok()?)),
+            Code::PATH_MTU_PLATEAU_TABLE => {
+                if bytes.is_empty() || !bytes.len().is_multiple_of(2) {
+                    return None;
+                }
+                PathMtuPlateauTable(bytes.chunks(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect())
+            },
+            Code::INTERFACE_MTU => InterfaceMtu(u16::from_be_bytes(bytes.try_into().ok()?)),
+            Code::ALL_SUBNETS_ARE_LOCAL => AllSubnetsAreLocal(flag(bytes)?),
+            Code::BROADCAST_ADDRESS => BroadcastAddress(ip(bytes)?),
+            Code::PERFORM_MASK_DISCOVERY => PerformMaskDiscovery(flag(bytes)?),
+            Code::MASK_SUPPLIER => MaskSupplier(flag(bytes)?),
+            Code::PERFORM_ROUTER_DISCOVERY => PerformRouterDiscovery(flag(bytes)?),
+            Code::ROUTER_SOLICITATION_ADDRESS => RouterSolicitationAddress(ip(bytes)?),
+            Code::STATIC_ROUTING_TABLE => StaticRoutes(ip_pairs(bytes)?),
+            Code::TRAILER_ENCAPSULATION => TrailerEncapsulation(flag(bytes)?),
+            Code::ARP_CACHE_TIMEOUT => ArpCacheTimeout(u32::from_be_bytes(bytes.try_into().ok()?)),
+            Code::ETHERNET_ENCAPSULATION => EthernetEncapsulation(flag(bytes)?),
+            Code::DEFAUL_TCPTTL => DefaultTcpTtl(byte(bytes)?),
+            Code::TCP_KEEPALIVE_INTERVAL => TcpKeepaliveInterval(u32::from_be_bytes(bytes.try_into().ok()?)),
+            Code::TCP_KEEPALIVE_GARBAGE => TcpKeepaliveGarbage(flag(bytes)?),
+            Code::NETWORK_INFORMATION_SERVICE_DOMAIN => NisDomain(string(bytes)?),
+            Code::NETWORK_INFORMATION_SERVERS => NisServers(ips(bytes, 4)?),
+            Code::NTP_SERVERS => NtpServers(ips(bytes, 4)?),
+            Code::VENDOR_SPECIFIC_INFORMATION => VendorSpecificInformation(bytes.to_vec()),
+            Code::NET_BIOS_OVER_TCPIP_NAME_SERVER => NetbiosNameServers(ips(bytes, 4)?),
+            Code::NET_BIOS_OVER_TCPIP_DATAGRAM_DISTRIBUTION_SERVER => NetbiosDatagramDistributionServers(ips(bytes, 4)?),
+            Code::NET_BIOS_OVER_TCPIP_NODE_TYPE => NetbiosNodeType(byte(bytes)?),
+            Code::NET_BIOS_OVER_TCPIP_SCOPE => NetbiosScope(string(bytes)?),
+            Code::X_WINDOW_SYSTEM_FONT_SERVER => XWindowFontServers(ips(bytes, 4)?),
+            Code::X_WINDOW_SYSTEM_DISPLAY_MANGER => XWindowDisplayManagers(ips(bytes, 4)?),
+            Code::REQUESTED_IP_ADDRESS => RequestedIpAddress(ip(bytes)?),
+            Code::IP_ADDRESS_LEASE_TIME => LeaseTime(u32::from_be_bytes(bytes.try_into().ok()?)),
+            Code::OVERLOAD => match byte(bytes)? {
+                overload @ 1..=3 => Overload(overload),
+                _ => return None,
+            },
+            Code::DHCP_MESSAGE_TYPE => MessageType(super::message_type::MessageType(byte(bytes)?)),
+            Code::SERVER_IDENTIFIER => ServerIdentifier(ip(bytes)?),
+            Code::PARAMETER_REQUEST_LIST => {
+                if bytes.is_empty() {
+                    return None;
+                }
+                ParameterRequestList(bytes.iter().map(|&c| Code(c)).collect())
+            },
+            Code::MESSAGE => Message(string(bytes)?),
+            Code::MAXIMUM_DHCP_MESSAGE_SIZE => {
+                let size = u16::from_be_bytes(bytes.try_into().ok()?);
+                if size < 576 {
+                    return None;
+                }
+                MaximumDhcpMessageSize(size)
+            },
+            Code::RENEW_TIME_VALUE => RenewalTime(u32::from_be_bytes(bytes.try_into().ok()?)),
+            Code::REBINDING_TIME_VALUE => RebindingTime(u32::from_be_bytes(bytes.try_into().ok()?)),
+            Code::CLASS_IDENTIFIER if !bytes.is_empty() => ClassIdentifier(bytes.to_vec()),
+            Code::CLIENT_IDENTIFIER if bytes.len() >= 2 => ClientIdentifier(bytes.to_vec()),
+            Code::NETWORK_INFORMATION_SERVICE_PLUS_DOMAIN => NisPlusDomain(string(bytes)?),
+            Code::NETWORK_INFORMATION_SERVICE_PLUS_SERVERS => NisPlusServers(ips(bytes, 4)?),
+            Code::TFTP_SERVER_NAME => TftpServerName(string(bytes)?),
+            Code::BOOTFILE_NAME => BootfileName(string(bytes)?),
+            Code::MOBILE_IP_HOME_AGENT => MobileIpHomeAgents(ips(bytes, 0)?),
+            Code::SIMPLE_MAIL_TRANSPORT_PROTOCOL_SERVER => SmtpServers(ips(bytes, 4)?),
+            Code::POST_OFFICE_PROTOCOL_SERVER => Pop3Servers(ips(bytes, 4)?),
+            Code::NETWORK_NEWS_TRANSPORT_PROTOCOL_SERVER => NntpServers(ips(bytes, 4)?),
+            Code::DEFAULT_WORLD_WIDE_WEB_SERVER => WwwServers(ips(bytes, 4)?),
+            Code::DEFAULT_FINGER_SERVER => FingerServers(ips(bytes, 4)?),
+            Code::DEFAULT_INTERNET_RELAY_CHAT_SERVER => IrcServers(ips(bytes, 4)?),
+            Code::STREET_TALK_SERVER => StreetTalkServers(ips(bytes, 4)?),
+            Code::STREET_TALK_DIRECTORY_ASSISTANCE_SERVER => StdaServers(ips(bytes, 4)?),
+            Code::RELAY_AGENT_INFORMATION => RelayAgentInformation(super::relay_agent_information::RelayAgentInformation::parse(bytes)?),
+            Code::CLASS_IDENTIFIER | Code::CLIENT_IDENTIFIER | Code::PAD | Code::END => return None,
+            code => Unknown(code, bytes.to_vec()),
+        };
+        Some(opt)
+    }
+
+    // Whether RFC 2132, or RFC 3046 for relay agent information, allows
+    // option `code` to be `len` bytes long. Codes neither defines may have
+    // any length.
+    pub fn valid_len(code: Code, len: usize) -> bool {
+        match code {
+            Code::SUBNET_MASK
+            | Code::TIME_OFFSET
+            | Code::SWAP_SERVER
+            | Code::PATH_MTU_AGING_TIMEOUT
+            | Code::BROADCAST_ADDRESS
+            | Code::ROUTER_SOLICITATION_ADDRESS
+            | Code::ARP_CACHE_TIMEOUT
+            | Code::TCP_KEEPALIVE_INTERVAL
+            | Code::REQUESTED_IP_ADDRESS
+            | Code::IP_ADDRESS_LEASE_TIME
+            | Code::SERVER_IDENTIFIER
+            | Code::RENEW_TIME_VALUE
+            | Code::REBINDING_TIME_VALUE => len == 4,
+            Code::BOOT_FILE_SIZE
+            | Code::MAXIMUM_DATAGRAM_ASSEMBLY_SIZE
+            | Code::INTERFACE_MTU
+            | Code::MAXIMUM_DHCP_MESSAGE_SIZE => len == 2,
+            Code::IP_FORWARDING
+            | Code::NON_LOCAL_SOURCE_ROUTING
+            | Code::DEFAULT_IPTTL
+            | Code::ALL_SUBNETS_ARE_LOCAL
+            | Code::PERFORM_MASK_DISCOVERY
+            | Code::MASK_SUPPLIER
+            | Code::PERFORM_ROUTER_DISCOVERY
+            | Code::TRAILER_ENCAPSULATION
+            | Code::ETHERNET_ENCAPSULATION
+            | Code::DEFAUL_TCPTTL
+            | Code::TCP_KEEPALIVE_GARBAGE
+            | Code::NET_BIOS_OVER_TCPIP_NODE_TYPE
+            | Code::OVERLOAD
+            | Code::DHCP_MESSAGE_TYPE => len == 1,
+            Code::MOBILE_IP_HOME_AGENT => len.is_multiple_of(4),
+            Code::POLICY_FILTER | Code::STATIC_ROUTING_TABLE => len >= 8 && len.is_multiple_of(8),
+            Code::PATH_MTU_PLATEAU_TABLE => len >= 2 && len.is_multiple_of(2),
+            Code::CLIENT_IDENTIFIER | Code::RELAY_AGENT_INFORMATION => len >= 2,
+            Code::ROUTER
+            | Code::TIME_SERVER
+            | Code::NAME_SERVER
+            | Code::DOMAIN_NAME_SERVER
+            | Code::LOG_SERVER
+            | Code::QUOTE_SERVER
+            | Code::LPR_SERVER
+            | Code::IMPRESS_SERVER
+            | Code::RESOURCE_LOCATION_SERVER
+            | Code::NETWORK_INFORMATION_SERVERS
+            | Code::NTP_SERVERS
+            | Code::NET_BIOS_OVER_TCPIP_NAME_SERVER
+            | Code::NET_BIOS_OVER_TCPIP_DATAGRAM_DISTRIBUTION_SERVER
+            | Code::X_WINDOW_SYSTEM_FONT_SERVER
+            | Code::X_WINDOW_SYSTEM_DISPLAY_MANGER
+            | Code::NETWORK_INFORMATION_SERVICE_PLUS_SERVERS
+            | Code::SIMPLE_MAIL_TRANSPORT_PROTOCOL_SERVER
+            | Code::POST_OFFICE_PROTOCOL_SERVER
+            | Code::NETWORK_NEWS_TRANSPORT_PROTOCOL_SERVER
+            | Code::DEFAULT_WORLD_WIDE_WEB_SERVER
+            | Code::DEFAULT_FINGER_SERVER
+            | Code::DEFAULT_INTERNET_RELAY_CHAT_SERVER
+            | Code::STREET_TALK_SERVER
+            | Code::STREET_TALK_DIRECTORY_ASSISTANCE_SERVER => len >= 4 && len.is_multiple_of(4),
+            Code::HOST_NAME
+            | Code::MERIT_DUMP_FILE
+            | Code::DOMAIN_NAME
+            | Code::ROOT_PATH
+            | Code::EXTENSIONS_PATH
+            | Code::NETWORK_INFORMATION_SERVICE_DOMAIN
+            | Code::NET_BIOS_OVER_TCPIP_SCOPE
+            | Code::PARAMETER_REQUEST_LIST
+            | Code::MESSAGE
+            | Code::CLASS_IDENTIFIER
+            | Code::NETWORK_INFORMATION_SERVICE_PLUS_DOMAIN
+            | Code::TFTP_SERVER_NAME
+            | Code::BOOTFILE_NAME => len >= 1,
+            _ => true,
+        }
+    }
+
+    pub fn code(&self) -> Code {
+        use DhcpOption::*;
+        match *self {
+            SubnetMask(_) => Code::SUBNET_MASK,
+            TimeOffset(_) => Code::TIME_OFFSET,
+            Routers(_) => Code::ROUTER,
+            TimeServers(_) => Code::TIME_SERVER,
+            NameServers(_) => Code::NAME_SERVER,
+            DomainNameServers(_) => Code::DOMAIN_NAME_SERVER,
+            LogServers(_) => Code::LOG_SERVER,
+            CookieServers(_) => Code::QUOTE_SERVER,
+            LprServers(_) => Code::LPR_SERVER,
+            ImpressServers(_) => Code::IMPRESS_SERVER,
+            ResourceLocationServers(_) => Code::RESOURCE_LOCATION_SERVER,
+            HostName(_) => Code::HOST_NAME,
+            BootFileSize(_) => Code::BOOT_FILE_SIZE,
+            MeritDumpFile(_) => Code::MERIT_DUMP_FILE,
+            DomainName(_) => Code::DOMAIN_NAME,
+            SwapServer(_) => Code::SWAP_SERVER,
+            RootPath(_) => Code::ROOT_PATH,
+            ExtensionsPath(_) => Code::EXTENSIONS_PATH,
+            IpForwarding(_) => Code::IP_FORWARDING,
+            NonLocalSourceRouting(_) => Code::NON_LOCAL_SOURCE_ROUTING,
+            PolicyFilter(_) => Code::POLICY_FILTER,
+            MaximumDatagramReassemblySize(_) => Code::MAXIMUM_DATAGRAM_ASSEMBLY_SIZE,
+            DefaultIpTtl(_) => Code::DEFAULT_IPTTL,
+            PathMtuAgingTimeout(_) => Code::PATH_MTU_AGING_TIMEOUT,
+            PathMtuPlateauTable(_) => Code::PATH_MTU_PLATEAU_TABLE,
+            InterfaceMtu(_) => Code::INTERFACE_MTU,
+            AllSubnetsAreLocal(_) => Code::ALL_SUBNETS_ARE_LOCAL,
+            BroadcastAddress(_) => Code::BROADCAST_ADDRESS,
+            PerformMaskDiscovery(_) => Code::PERFORM_MASK_DISCOVERY,
+            MaskSupplier(_) => Code::MASK_SUPPLIER,
+            PerformRouterDiscovery(_) => Code::PERFORM_ROUTER_DISCOVERY,
+            RouterSolicitationAddress(_) => Code::ROUTER_SOLICITATION_ADDRESS,
+            StaticRoutes(_) => Code::STATIC_ROUTING_TABLE,
+            TrailerEncapsulation(_) => Code::TRAILER_ENCAPSULATION,
+            ArpCacheTimeout(_) => Code::ARP_CACHE_TIMEOUT,
+            EthernetEncapsulation(_) => Code::ETHERNET_ENCAPSULATION,
+            DefaultTcpTtl(_) => Code::DEFAUL_TCPTTL,
+            TcpKeepaliveInterval(_) => Code::TCP_KEEPALIVE_INTERVAL,
+            TcpKeepaliveGarbage(_) => Code::TCP_KEEPALIVE_GARBAGE,
+            NisDomain(_) => Code::NETWORK_INFORMATION_SERVICE_DOMAIN,
+            NisServers(_) => Code::NETWORK_INFORMATION_SERVERS,
+            NtpServers(_) => Code::NTP_SERVERS,
+            VendorSpecificInformation(_) => Code::VENDOR_SPECIFIC_INFORMATION,
+            NetbiosNameServers(_) => Code::NET_BIOS_OVER_TCPIP_NAME_SERVER,
+            NetbiosDatagramDistributionServers(_) => Code::NET_BIOS_OVER_TCPIP_DATAGRAM_DISTRIBUTION_SERVER,
+            NetbiosNodeType(_) => Code::NET_BIOS_OVER_TCPIP_NODE_TYPE,
+            NetbiosScope(_) => Code::NET_BIOS_OVER_TCPIP_SCOPE,
+            XWindowFontServers(_) => Code::X_WINDOW_SYSTEM_FONT_SERVER,
+            XWindowDisplayManagers(_) => Code::X_WINDOW_SYSTEM_DISPLAY_MANGER,
+            RequestedIpAddress(_) => Code::REQUESTED_IP_ADDRESS,
+            LeaseTime(_) => Code::IP_ADDRESS_LEASE_TIME,
+            Overload(_) => Code::OVERLOAD,
+            MessageType(_) => Code::DHCP_MESSAGE_TYPE,
+            ServerIdentifier(_) => Code::SERVER_IDENTIFIER,
+            ParameterRequestList(_) => Code::PARAMETER_REQUEST_LIST,
+            Message(_) => Code::MESSAGE,
+            MaximumDhcpMessageSize(_) => Code::MAXIMUM_DHCP_MESSAGE_SIZE,
+            RenewalTime(_) => Code::RENEW_TIME_VALUE,
+            RebindingTime(_) => Code::REBINDING_TIME_VALUE,
+            ClassIdentifier(_) => Code::CLASS_IDENTIFIER,
+            ClientIdentifier(_) => Code::CLIENT_IDENTIFIER,
+            NisPlusDomain(_) => Code::NETWORK_INFORMATION_SERVICE_PLUS_DOMAIN,
+            NisPlusServers(_) => Code::NETWORK_INFORMATION_SERVICE_PLUS_SERVERS,
+            TftpServerName(_) => Code::TFTP_SERVER_NAME,
+            BootfileName(_) => Code::BOOTFILE_NAME,
+            MobileIpHomeAgents(_) => Code::MOBILE_IP_HOME_AGENT,
+            SmtpServers(_) => Code::SIMPLE_MAIL_TRANSPORT_PROTOCOL_SERVER,
+            Pop3Servers(_) => Code::POST_OFFICE_PROTOCOL_SERVER,
+            NntpServers(_) => Code::NETWORK_NEWS_TRANSPORT_PROTOCOL_SERVER,
+            WwwServers(_) => Code::DEFAULT_WORLD_WIDE_WEB_SERVER,
+            FingerServers(_) => Code::DEFAULT_FINGER_SERVER,
+            IrcServers(_) => Code::DEFAULT_INTERNET_RELAY_CHAT_SERVER,
+            StreetTalkServers(_) => Code::STREET_TALK_SERVER,
+            StdaServers(_) => Code::STREET_TALK_DIRECTORY_ASSISTANCE_SERVER,
+            RelayAgentInformation(_) => Code::RELAY_AGENT_INFORMATION,
+            Unknown(code, _) => code,
+        }
+    }
+
+    // The value of the option as it goes on the wire.
+    pub fn encode(&self) -> Vec<u8> {
+        use DhcpOption::*;
+        match self {
+            SubnetMask(addr)
+            | SwapServer(addr)
+            | BroadcastAddress(addr)
+            | RouterSolicitationAddress(addr)
+            | RequestedIpAddress(addr)
+            | ServerIdentifier(addr) => addr.octets().to_vec(),
+            Routers(addrs)
+            | TimeServers(addrs)
+            | NameServers(addrs)
+            | DomainNameServers(addrs)
+            | LogServers(addrs)
+            | CookieServers(addrs)
+            | LprServers(addrs)
+            | ImpressServers(addrs)
+            | ResourceLocationServers(addrs)
+            | NisServers(addrs)
+            | NtpServers(addrs)
+            | NetbiosNameServers(addrs)
+            | NetbiosDatagramDistributionServers(addrs)
+            | XWindowFontServers(addrs)
+            | XWindowDisplayManagers(addrs)
+            | NisPlusServers(addrs)
+            | MobileIpHomeAgents(addrs)
+            | SmtpServers(addrs)
+            | Pop3Servers(addrs)
+            | NntpServers(addrs)
+            | WwwServers(addrs)
+            | FingerServers(addrs)
+            | IrcServers(addrs)
+            | StreetTalkServers(addrs)
+            | StdaServers(addrs) => addrs.iter().flat_map(|addr| addr.octets().to_vec()).collect(),
+            PolicyFilter(pairs) | StaticRoutes(pairs) => pairs.iter()
+                .flat_map(|(a, b)| a.octets().iter().chain(&b.octets()).copied().collect::<Vec<_>>())
+                .collect(),
+            HostName(s)
+            | MeritDumpFile(s)
+            | DomainName(s)
+            | RootPath(s)
+            | ExtensionsPath(s)
+            | NisDomain(s)
+            | NetbiosScope(s)
+            | Message(s)
+            | NisPlusDomain(s)
+            | TftpServerName(s)
+            | BootfileName(s) => s.as_bytes().to_vec(),
+            IpForwarding(b)
+            | NonLocalSourceRouting(b)
+            | AllSubnetsAreLocal(b)
+            | PerformMaskDiscovery(b)
+            | MaskSupplier(b)
+            | PerformRouterDiscovery(b)
+            | TrailerEncapsulation(b)
+            | EthernetEncapsulation(b)
+            | TcpKeepaliveGarbage(b) => vec![u8::from(*b)],
+            DefaultIpTtl(n) | DefaultTcpTtl(n) | NetbiosNodeType(n) | Overload(n) => vec![*n],
+            BootFileSize(n) | MaximumDatagramReassemblySize(n) | InterfaceMtu(n) | MaximumDhcpMessageSize(n) => n.to_be_bytes().to_vec(),
+            PathMtuAgingTimeout(n)
+            | ArpCacheTimeout(n)
+            | TcpKeepaliveInterval(n)
+            | LeaseTime(n)
+            | RenewalTime(n)
+            | RebindingTime(n) => n.to_be_bytes().to_vec(),
+            TimeOffset(n) => n.to_be_bytes().to_vec(),
+            RelayAgentInformation(info) => info.encode(),
+            PathMtuPlateauTable(mtus) => mtus.iter().flat_map(|mtu| mtu.to_be_bytes().to_vec()).collect(),
+            MessageType(super::message_type::MessageType(typ)) => vec![*typ],
+            ParameterRequestList(codes) => codes.iter().map(|&Code(code)| code).collect(),
+            VendorSpecificInformation(bytes)
+            | ClassIdentifier(bytes)
+            | ClientIdentifier(bytes)
+            | Unknown(_, bytes) => bytes.clone(),
+        }
+    }
+}
+
+pub trait AddDhcpOptionExt: AddBytesExt {
+    fn add_option(&mut self, opt: &DhcpOption) {
+        self.add_bytes(opt.code(), &opt.encode());
+    }
+}
+
+impl<T: AddBytesExt> AddDhcpOptionExt for T {}
+
+fn byte(bytes: &[u8]) -> Option<u8> {
+    match *bytes {
+        [b] => Some(b),
+        _ => None,
+    }
+}
+
+fn flag(bytes: &[u8]) -> Option<bool> {
+    match byte(bytes)? {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    }
+}
+
+fn ip(bytes: &[u8]) -> Option<Ipv4Addr> {
+    let octets: [u8; 4] = bytes.try_into().ok()?;
+    Some(Ipv4Addr::from(octets))
+}
+
+fn ips(bytes: &[u8], min_len: usize) -> Option<Vec<Ipv4Addr>> {
+    if bytes.len() < min_len || !bytes.len().is_multiple_of(4) {
+        return None;
+    }
+    bytes.chunks(4).map(ip).collect()
+}
+
+fn ip_pairs(bytes: &[u8]) -> Option<Vec<(Ipv4Addr, Ipv4Addr)>> {
+    if bytes.len() < 8 || !bytes.len().is_multiple_of(8) {
+        return None;
+    }
+    bytes.chunks(8).map(|pair| Some((ip(&pair[..4])?, ip(&pair[4..])?))).collect()
+}
+
+fn string(bytes: &[u8]) -> Option<String> {
+    if bytes.is_empty() {
+        return None;
+    }
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Whatever `parse` decodes must have a length `valid_len` allows.
+    #[test]
+    fn valid_len_agrees_with_parse() {
+        for code in 1..=254 {
+            for len in 0..=24 {
+                let mut bytes = vec![b'a'; len];
+                if code == Code::OVERLOAD.0 || code == Code::NET_BIOS_OVER_TCPIP_NODE_TYPE.0 {
+                    bytes.iter_mut().for_each(|b| *b = 1);
+                }
+                if DhcpOption::parse(Code(code), &bytes).is_some() {
+                    assert!(DhcpOption::valid_len(Code(code), len), "code {} len {}", code, len);
+                }
+            }
+        }
+    }
+
+    // One of each variant.
+    fn samples() -> Vec<DhcpOption> {
+        use DhcpOption as O;
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+        let ips = vec![ip, Ipv4Addr::new(10, 0, 0, 2)];
+        let pairs = vec![(ip, Ipv4Addr::new(255, 255, 255, 0)), (Ipv4Addr::new(10, 1, 0, 0), ip)];
+        let s = "example.org".to_owned();
+        let mut info = RelayAgentInformation::new();
+        info.push(super::super::relay_agent_information::SubCode::AGENT_CIRCUIT_ID, &[0, 4]).unwrap();
+        vec![
+            O::SubnetMask(ip),
+            O::TimeOffset(-3600),
+            O::Routers(ips.clone()),
+            O::TimeServers(ips.clone()),
+            O::NameServers(ips.clone()),
+            O::DomainNameServers(ips.clone()),
+            O::LogServers(ips.clone()),
+            O::CookieServers(ips.clone()),
+            O::LprServers(ips.clone()),
+            O::ImpressServers(ips.clone()),
+            O::ResourceLocationServers(ips.clone()),
+            O::HostName(s.clone()),
+            O::BootFileSize(1024),
+            O::MeritDumpFile(s.clone()),
+            O::DomainName(s.clone()),
+            O::SwapServer(ip),
+            O::RootPath(s.clone()),
+            O::ExtensionsPath(s.clone()),
+            O::IpForwarding(true),
+            O::NonLocalSourceRouting(false),
+            O::PolicyFilter(pairs.clone()),
+            O::MaximumDatagramReassemblySize(1500),
+            O::DefaultIpTtl(64),
+            O::PathMtuAgingTimeout(600),
+            O::PathMtuPlateauTable(vec![1500, 576]),
+            O::InterfaceMtu(9000),
+            O::AllSubnetsAreLocal(true),
+            O::BroadcastAddress(ip),
+            O::PerformMaskDiscovery(false),
+            O::MaskSupplier(true),
+            O::PerformRouterDiscovery(true),
+            O::RouterSolicitationAddress(ip),
+            O::StaticRoutes(pairs),
+            O::TrailerEncapsulation(false),
+            O::ArpCacheTimeout(60),
+            O::EthernetEncapsulation(true),
+            O::DefaultTcpTtl(128),
+            O::TcpKeepaliveInterval(7200),
+            O::TcpKeepaliveGarbage(false),
+            O::NisDomain(s.clone()),
+            O::NisServers(ips.clone()),
+            O::NtpServers(ips.clone()),
+            O::VendorSpecificInformation(vec![1, 2, 3]),
+            O::NetbiosNameServers(ips.clone()),
+            O::NetbiosDatagramDistributionServers(ips.clone()),
+            O::NetbiosNodeType(8),
+            O::NetbiosScope(s.clone()),
+            O::XWindowFontServers(ips.clone()),
+            O::XWindowDisplayManagers(ips.clone()),
+            O::RequestedIpAddress(ip),
+            O::LeaseTime(3600),
+            O::Overload(3),
+            O::MessageType(MessageType::DHCPACK),
+            O::ServerIdentifier(ip),
+            O::ParameterRequestList(vec![Code::SUBNET_MASK, Code::ROUTER, Code::DOMAIN_NAME_SERVER]),
+            O::Message(s.clone()),
+            O::MaximumDhcpMessageSize(1500),
+            O::RenewalTime(1800),
+            O::RebindingTime(3150),
+            O::ClassIdentifier(b"PXEClient".to_vec()),
+            O::ClientIdentifier(vec![1, 2, 0, 0, 0, 0, 1]),
+            O::NisPlusDomain(s.clone()),
+            O::NisPlusServers(ips.clone()),
+            O::TftpServerName(s.clone()),
+            O::BootfileName("pxelinux.0".to_owned()),
+            O::MobileIpHomeAgents(vec![]),
+            O::SmtpServers(ips.clone()),
+            O::Pop3Servers(ips.clone()),
+            O::NntpServers(ips.clone()),
+            O::WwwServers(ips.clone()),
+            O::FingerServers(ips.clone()),
+            O::IrcServers(ips.clone()),
+            O::StreetTalkServers(ips.clone()),
+            O::StdaServers(ips),
+            O::RelayAgentInformation(info),
+            O::Unknown(Code(224), vec![0xde, 0xad]),
+        ]
+    }
+
+    #[test]
+    fn round_trips_every_variant() {
+        for opt in samples() {
+            let bytes = opt.encode();
+            assert!(DhcpOption::valid_len(opt.code(), bytes.len()), "{:?}", opt);
+            assert_eq!(DhcpOption::parse(opt.code(), &bytes), Some(opt.clone()));
+        }
+    }
+
+    // A BOOTREQUEST carrying only `opt`, padded to the minimum size.
+    fn message(opt: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0u8; 236];
+        buf[0] = 1;
+        buf.extend_from_slice(&[99, 130, 83, 99]);
+        buf.extend_from_slice(opt);
+        buf.push(255);
+        buf.resize(buf.len().max(300), 0);
+        buf
+    }
+
+    #[test]
+    fn bad_lengths_are_errors_or_quirks() {
+        use crate::error::ErrorKind;
+        use crate::parser::{Mode, Parser, Quirks};
+        // One too short or too long for every option with a fixed shape.
+        // OVERLOAD is left out: it sends the parser into `file` and `sname`.
+        let mut bad = Vec::new();
+        for opt in samples().into_iter().filter(|opt| opt.code() != Code::OVERLOAD) {
+            let Code(code) = opt.code();
+            let bytes = opt.encode();
+            for len in [bytes.len().saturating_sub(1), bytes.len() + 1].iter().copied() {
+                if !DhcpOption::valid_len(Code(code), len) {
+                    let mut value = bytes.clone();
+                    value.resize(len, 0);
+                    bad.push((code, value));
+                }
+            }
+        }
+        assert!(bad.len() > 50);
+        for (code, value) in bad {
+            let mut opt = vec![code, value.len() as u8];
+            opt.extend_from_slice(&value);
+            let buf = message(&opt);
+            let err = Parser::new(Mode::Strict).parse(&buf).err().unwrap_or_else(|| panic!("code {}: strict parse accepted a bad length", code));
+            assert_eq!(err.kind(), ErrorKind::LengthMismatch(Code(code), value.len()), "code {}", code);
+            let (_, quirks) = Parser::new(Mode::Lenient).parse(&buf).unwrap();
+            assert_eq!(quirks, Quirks { bad_length: 1, ..Quirks::default() }, "code {}", code);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+use std::ops::AddAssign;
+
+use super::error::Error;
+use super::message::{self, Message};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    // Everything RFC 2131 and RFC 2132 require, as `Message::parse` checks.
+    #[default]
+    Strict,
+    // Tolerates the quirks counted by `Quirks`.
+    Lenient,
+}
+
+// How many messages showed each quirk a lenient parser tolerates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    // Shorter than `Message::MIN_SIZE`, but with the header and magic
+    // cookie complete.
+    pub short_message: u64,
+    // A field holding options runs out without an END option.
+    pub missing_end: u64,
+    // Something other than padding follows END.
+    pub trailing_garbage: u64,
+    // An option's value has a length its code cannot have. The message is
+    // kept; `OptionSet::remove_malformed` drops such options.
+    pub bad_length: u64,
+}
+
+impl Quirks {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl AddAssign for Quirks {
+    fn add_assign(&mut self, other: Self) {
+        self.short_message += other.short_message;
+        self.missing_end += other.missing_end;
+        self.trailing_garbage += other.trailing_garbage;
+        self.bad_length += other.bad_length;
+    }
+}
+
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = [
+            ("short message", self.short_message),
+            ("missing END", self.missing_end),
+            ("garbage after END", self.trailing_garbage),
+            ("bad option length", self.bad_length),
+        ];
+        let mut sep = "";
+        for (name, count) in counts.iter().filter(|(_, count)| *count > 0) {
+            write!(f, "{}{} ({})", sep, name, count)?;
+            sep = ", ";
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Parser {
+    mode: Mode,
+    seen: Quirks,
+}
+
+impl Parser {
+    pub fn new(mode: Mode) -> Self {
+        Self { mode, seen: Quirks::default() }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // The quirks tolerated so far, summed over every message parsed.
+    pub fn seen(&self) -> Quirks {
+        self.seen
+    }
+
+    // Parses `buf` as `Message::parse` does, except that a lenient parser
+    // tolerates quirks. Returns the message along with the quirks it showed,
+    // which are also added to `seen`.
+    pub fn parse<'a>(&mut self, buf: &'a [u8]) -> Result<(Message<&'a [u8]>, Quirks), Error> {
+        let (msg, quirks) = message::check(buf, self.mode)?;
+        self.seen += quirks;
+        Ok((msg, quirks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::option::Code;
+    use crate::options::OptionSet;
+    use crate::options::server_identifier::GetServerIdentifierExt;
+    use crate::options::message_type::{GetMessageTypeExt, MessageType};
+
+    // A DISCOVER with `opts` after the message type, padded to the minimum
+    // size.
+    fn message(opts: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0u8; 236];
+        buf[0] = 1;
+        buf.extend_from_slice(&[99, 130, 83, 99, 53, 1, 1]);
+        buf.extend_from_slice(opts);
+        buf.resize(300, 0);
+        buf
+    }
+
+    #[test]
+    fn strict_rejects_bad_length() {
+        let buf = message(&[54, 3, 10, 0, 0, 255]);
+        let err = Parser::new(Mode::Strict).parse(&buf).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::LengthMismatch(Code::SERVER_IDENTIFIER, 3));
+    }
+
+    #[test]
+    fn lenient_counts_and_skips_bad_length() {
+        let buf = message(&[54, 3, 10, 0, 0, 12, 1, b'h', 255]);
+        let mut parser = Parser::new(Mode::Lenient);
+        let (m, quirks) = parser.parse(&buf).unwrap();
+        assert_eq!(quirks, Quirks { bad_length: 1, ..Quirks::default() });
+        let mut opts = OptionSet::from_message(&m).unwrap();
+        assert_eq!(opts.remove_malformed(), 1);
+        assert_eq!(opts.get_server_identifier(), None);
+        assert_eq!(opts.get_message_type(), Some(MessageType::DHCPDISCOVER));
+        assert_eq!(opts.get(Code::HOST_NAME), Some(&b"h"[..]));
+        parser.parse(&buf).unwrap();
+        assert_eq!(parser.seen().bad_length, 2);
+        assert_eq!(parser.seen().to_string(), "bad option length (2)");
+    }
+
+    #[test]
+    fn lenient_tolerates_missing_end_and_garbage() {
+        let (_, quirks) = Parser::new(Mode::Lenient).parse(&message(&[255, 1])).unwrap();
+        assert_eq!(quirks, Quirks { trailing_garbage: 1, ..Quirks::default() });
+        let mut buf = message(&[]);
+        buf.truncate(243);
+        let (_, quirks) = Parser::new(Mode::Lenient).parse(&buf).unwrap();
+        assert_eq!(quirks, Quirks { short_message: 1, missing_end: 1, ..Quirks::default() });
+        assert!(Parser::new(Mode::Strict).parse(&buf).is_err());
+    }
+}
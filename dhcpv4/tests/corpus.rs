@@ -0,0 +1,130 @@
+// Replays the fuzzing corpus in `fuzz/corpus` through the same checks as
+// the fuzz targets in `fuzz/fuzz_targets`, so that inputs that once crashed
+// a decoder are tried on every `cargo test`, without a nightly toolchain.
+
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use dhcpv4::Message;
+use dhcpv4::option::Code;
+use dhcpv4::options::{self, DhcpOption, OptionMap, OptionSet, Options};
+use dhcpv4::options::{
+    bytes::*,
+    client_identifier::*,
+    domain_name::*,
+    host_name::*,
+    ip::*,
+    ips::*,
+    lease_time::*,
+    maximum_dhcp_message_size::*,
+    message_type::*,
+    parameter_request_list::*,
+    relay_agent_information::*,
+    requested_ip_address::*,
+    routers::*,
+    server_identifier::*,
+    subnet_mask::*,
+};
+use dhcpv4::options::domain_name_servers::GetDomainNameExt as GetDomainNameServersExt;
+use dhcpv4::parser::{Mode, Parser};
+
+// Runs `check` on every input in the corpus of fuzz target `target`,
+// naming the input that fails.
+fn replay<F: Fn(&[u8])>(target: &str, check: F) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus").join(target);
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("{}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "{} is empty", dir.display());
+    for path in paths {
+        let data = fs::read(&path).unwrap();
+        if panic::catch_unwind(AssertUnwindSafe(|| check(&data))).is_err() {
+            panic!("{} fails", path.display());
+        }
+    }
+}
+
+#[test]
+fn message() {
+    replay("message", |data| {
+        if let Some(m) = Message::new(data) {
+            let hdr = m.header();
+            let _ = (hdr.op_code(), hdr.htype(), hdr.hlen(), hdr.hops(), hdr.xid(), hdr.secs(), hdr.flags());
+            let _ = (hdr.ciaddr(), hdr.yiaddr(), hdr.siaddr(), hdr.giaddr(), hdr.chaddr(), hdr.sname(), hdr.file());
+            let _ = m.options().try_iter().map(Iterator::count);
+            let _ = m.try_iter_options().map(Iterator::count);
+        }
+        let strict = Message::parse(data);
+        let lenient = Parser::new(Mode::Lenient).parse(data);
+        assert!(strict.is_err() || lenient.is_ok());
+        if let Ok((m, _)) = lenient {
+            let set = OptionSet::from_message(&m).expect("parsed message with a bad cookie");
+            for (code, value) in set.iter() {
+                if let Some(opt) = DhcpOption::parse(code, value) {
+                    assert_eq!(opt.code(), code);
+                    assert_eq!(opt.encode(), value);
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn options() {
+    replay("options", |data| {
+        let opts = match Options::new(data) {
+            Some(opts) => opts,
+            None => return,
+        };
+        let _ = opts.magic_cookie();
+        if let Some(iter) = opts.try_iter() {
+            for opt in iter {
+                let _ = opt.code();
+                if let Some(value) = opt.value() {
+                    let _ = (value.len(), value.value());
+                }
+            }
+            let _ = options::concatenate(opts.try_iter().unwrap());
+        }
+    });
+}
+
+fn get_all<M: OptionMap>(map: &mut M, code: Code) {
+    let _ = map.get_bytes(code);
+    let _ = map.get_ip(code);
+    let _ = map.get_ips(code).map(Iterator::count);
+    let _ = map.get_client_identifier();
+    let _ = map.get_domain_name();
+    let _ = map.get_domain_name_servers().map(Iterator::count);
+    let _ = map.get_host_name();
+    let _ = map.get_lease_time();
+    let _ = map.get_maximum_dhcp_message_size();
+    let _ = map.get_message_type();
+    let _ = map.get_parameter_request_list().map(Iterator::count);
+    let _ = map.get_relay_agent_information();
+    let _ = map.get_requested_ip_address();
+    let _ = map.get_routers().map(Iterator::count);
+    let _ = map.get_server_identifier();
+    let _ = map.get_subnet_mask();
+}
+
+#[test]
+fn get_ext() {
+    replay("get_ext", |data| {
+        let (&code, data) = match data.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+        let mut buf = Options::<()>::MAGIC_COOKIE.to_vec();
+        buf.extend_from_slice(data);
+        buf.resize(buf.len().max(Options::<()>::MIN_SIZE), 0);
+        let opts = Options::new(&buf[..]).unwrap();
+        let mut map = options::concatenate(opts.try_iter().unwrap());
+        get_all(&mut map, Code(code));
+        let mut set: OptionSet = opts.try_iter().unwrap().collect();
+        get_all(&mut set, Code(code));
+    });
+}